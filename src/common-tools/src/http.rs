@@ -0,0 +1,36 @@
+use rmcp::transport::streamable_http_server::{
+    session::local::LocalSessionManager, StreamableHttpServerConfig, StreamableHttpService,
+};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::{CommonToolsServer, ServerConfig};
+
+pub const MCP_ENDPOINT: &str = "/mcp";
+
+/// Serves the MCP streamable HTTP transport on `listener` until `shutdown` is
+/// cancelled.
+///
+/// Every client session gets its own `CommonToolsServer`, so per-session state
+/// is never shared between agents connected to the same process.
+pub async fn serve_http(
+    listener: TcpListener,
+    config: ServerConfig,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    let service = StreamableHttpService::new(
+        move || Ok(CommonToolsServer::with_config(config.clone())),
+        Arc::new(LocalSessionManager::default()),
+        StreamableHttpServerConfig {
+            cancellation_token: shutdown.child_token(),
+            ..Default::default()
+        },
+    );
+
+    let router = axum::Router::new().nest_service(MCP_ENDPOINT, service);
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move { shutdown.cancelled_owned().await })
+        .await
+}
@@ -1,5 +1,6 @@
+pub mod http;
 pub mod server;
 pub mod tools;
 
-pub use server::CommonToolsServer;
+pub use server::{CommonToolsServer, ServerConfig};
 pub use tools::*;
@@ -1,8 +1,120 @@
 use anyhow::Result;
-use common_tools::CommonToolsServer;
+use clap::{Parser, ValueEnum};
+use common_tools::{
+    http, CommandPolicy, CommonToolsServer, ResourceLimits, ServerConfig, Workspace,
+    DEFAULT_COMMAND_TIMEOUT_MS, DEFAULT_MAX_OUTPUT_BYTES,
+};
 use rmcp::{transport::stdio, ServiceExt};
+use std::{future::Future, net::SocketAddr, path::PathBuf};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{self, EnvFilter};
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Transport {
+    Stdio,
+    Http,
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Common Tools MCP Server")]
+struct Args {
+    /// Transport used to talk to MCP clients
+    #[arg(
+        long,
+        value_enum,
+        default_value = "stdio",
+        env = "COMMON_TOOLS_TRANSPORT"
+    )]
+    transport: Transport,
+
+    /// Address to listen on when using the http transport
+    #[arg(long, default_value = "127.0.0.1:8000", env = "COMMON_TOOLS_BIND")]
+    bind: SocketAddr,
+
+    /// Restrict all file tools to this directory
+    #[arg(long, env = "COMMON_TOOLS_WORKSPACE_ROOT")]
+    workspace_root: Option<PathBuf>,
+
+    /// Additional directories that may be read but not modified
+    #[arg(
+        long = "read-only-root",
+        env = "COMMON_TOOLS_READ_ONLY_ROOTS",
+        value_delimiter = ',',
+        requires = "workspace_root"
+    )]
+    read_only_roots: Vec<PathBuf>,
+
+    /// Default execute_command timeout in milliseconds; 0 disables it
+    #[arg(
+        long,
+        default_value_t = DEFAULT_COMMAND_TIMEOUT_MS,
+        env = "COMMON_TOOLS_COMMAND_TIMEOUT_MS"
+    )]
+    command_timeout_ms: u64,
+
+    /// Default cap on bytes of stdout and of stderr returned by execute_command
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_OUTPUT_BYTES,
+        env = "COMMON_TOOLS_MAX_OUTPUT_BYTES"
+    )]
+    max_output_bytes: usize,
+
+    /// CPU seconds each command may use (Linux only)
+    #[arg(long, env = "COMMON_TOOLS_LIMIT_CPU_SECS")]
+    limit_cpu_secs: Option<u64>,
+
+    /// Milliseconds each command may run, including background processes
+    #[arg(long, env = "COMMON_TOOLS_LIMIT_WALL_TIME_MS")]
+    limit_wall_time_ms: Option<u64>,
+
+    /// Virtual memory in bytes each command may use (Linux only)
+    #[arg(long, env = "COMMON_TOOLS_LIMIT_MEMORY_BYTES")]
+    limit_memory_bytes: Option<u64>,
+
+    /// Maximum processes for the server's user while a command runs (Linux only)
+    #[arg(long, env = "COMMON_TOOLS_LIMIT_PROCESSES")]
+    limit_processes: Option<u64>,
+
+    /// Largest file in bytes a command may write (Linux only)
+    #[arg(long, env = "COMMON_TOOLS_LIMIT_FILE_SIZE_BYTES")]
+    limit_file_size_bytes: Option<u64>,
+
+    /// TOML file of allow/confirm/deny rules for execute_command
+    #[arg(long, env = "COMMON_TOOLS_POLICY_FILE")]
+    policy_file: Option<PathBuf>,
+}
+
+impl Args {
+    fn workspace(&self) -> Result<Workspace> {
+        let Some(root) = &self.workspace_root else {
+            return Ok(Workspace::unrestricted());
+        };
+
+        Ok(Workspace::new(root, &self.read_only_roots)?)
+    }
+
+    fn server_config(&self) -> Result<ServerConfig> {
+        Ok(ServerConfig {
+            workspace: self.workspace()?,
+            default_command_timeout_ms: Some(self.command_timeout_ms).filter(|&ms| ms > 0),
+            max_command_output_bytes: self.max_output_bytes,
+            resource_limits: ResourceLimits {
+                cpu_time_secs: self.limit_cpu_secs,
+                wall_time_ms: self.limit_wall_time_ms,
+                memory_bytes: self.limit_memory_bytes,
+                max_processes: self.limit_processes,
+                max_file_size_bytes: self.limit_file_size_bytes,
+            },
+            command_policy: match &self.policy_file {
+                Some(path) => CommandPolicy::load(path)?,
+                None => CommandPolicy::allow_all(),
+            },
+        })
+    }
+}
+
 fn initialize_tracing() {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
@@ -11,13 +123,8 @@ fn initialize_tracing() {
         .init();
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    initialize_tracing();
-
-    tracing::info!("Starting Common Tools MCP Server");
-
-    let server = CommonToolsServer::new();
+async fn serve_stdio(config: ServerConfig) -> Result<()> {
+    let server = CommonToolsServer::with_config(config);
 
     let service = server.serve(stdio()).await.inspect_err(|e| {
         tracing::error!("serving error: {:?}", e);
@@ -26,3 +133,62 @@ async fn main() -> Result<()> {
     service.waiting().await?;
     Ok(())
 }
+
+async fn serve_http(config: ServerConfig, bind: SocketAddr) -> Result<()> {
+    let signal = shutdown_signal()?;
+    let listener = TcpListener::bind(bind).await?;
+    tracing::info!(
+        "Listening on http://{}{}",
+        listener.local_addr()?,
+        http::MCP_ENDPOINT
+    );
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            signal.await;
+            tracing::info!("Shutting down");
+            shutdown.cancel();
+        }
+    });
+
+    http::serve_http(listener, config, shutdown).await?;
+    Ok(())
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM from a service manager. SIGTERM
+/// is caught from the moment this returns, before the server starts listening.
+fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+    Ok(async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    initialize_tracing();
+
+    tracing::info!("Starting Common Tools MCP Server");
+
+    let config = args.server_config()?;
+    if let Some(root) = config.workspace.root() {
+        tracing::info!("Restricting file tools to {}", root.display());
+    }
+
+    match args.transport {
+        Transport::Stdio => serve_stdio(config).await,
+        Transport::Http => serve_http(config, args.bind).await,
+    }
+}
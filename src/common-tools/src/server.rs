@@ -2,12 +2,50 @@ use crate::tools::*;
 use rmcp::{
     handler::server::{router::tool::ToolRouter, wrapper::Parameters},
     model::*,
+    service::{Peer, RoleServer},
     tool, tool_handler, tool_router, ErrorData as McpError, ServerHandler,
 };
+use std::path::PathBuf;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio_util::sync::CancellationToken;
+
+/// Settings shared by every tool call a server handles.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub workspace: Workspace,
+    /// Applied to `execute_command` calls that don't pass `timeout_ms`.
+    pub default_command_timeout_ms: Option<u64>,
+    /// Applied to `execute_command` calls that don't pass `max_output_bytes`.
+    pub max_command_output_bytes: usize,
+    /// Applied to every `execute_command` call, which may only tighten them,
+    /// and to shell sessions.
+    pub resource_limits: ResourceLimits,
+    /// Decides which commands `execute_command` and shell sessions may run.
+    pub command_policy: CommandPolicy,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            workspace: Workspace::unrestricted(),
+            default_command_timeout_ms: Some(DEFAULT_COMMAND_TIMEOUT_MS),
+            max_command_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+            resource_limits: ResourceLimits::default(),
+            command_policy: CommandPolicy::allow_all(),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct CommonToolsServer {
     tool_router: ToolRouter<Self>,
+    config: ServerConfig,
+    processes: ProcessRegistry,
+    shell_sessions: ShellSessions,
+    /// Where truncated command output is spilled. It is deleted with the last
+    /// clone of the server, and each HTTP session has a server of its own.
+    output_dir: Option<Arc<TempDir>>,
 }
 
 impl Default for CommonToolsServer {
@@ -19,23 +57,59 @@ impl Default for CommonToolsServer {
 #[tool_router]
 impl CommonToolsServer {
     pub fn new() -> Self {
+        Self::with_config(ServerConfig::default())
+    }
+
+    pub fn with_config(mut config: ServerConfig) -> Self {
+        // Without somewhere to spill to, long output is still truncated.
+        let output_dir = match create_output_dir(&config.workspace) {
+            Ok((dir, workspace)) => {
+                config.workspace = workspace;
+                Some(Arc::new(dir))
+            }
+            Err(e) => {
+                tracing::warn!("cannot create a directory for command output: {}", e);
+                None
+            }
+        };
+
         Self {
             tool_router: Self::tool_router(),
+            config,
+            processes: ProcessRegistry::new(),
+            shell_sessions: ShellSessions::new(),
+            output_dir,
         }
     }
 
-    #[tool(description = "Read the contents of a text file from the file system")]
+    fn output_dir(&self) -> Option<PathBuf> {
+        self.output_dir.as_ref().map(|dir| dir.path().to_path_buf())
+    }
+
+    #[tool(
+        description = "Read a text file with line numbers, optionally a range of lines via offset and limit. Images and PDFs are returned as image or embedded resource content"
+    )]
     async fn read_file(
         &self,
         Parameters(params): Parameters<ReadFileParams>,
     ) -> Result<CallToolResult, McpError> {
-        ReadFileTool { path: params.path }
-            .call_tool()
-            .await
-            .map_err(|e| McpError::internal_error(e.to_string(), None))
+        ReadFileTool {
+            path: params.path,
+            offset: params.offset.unwrap_or(1),
+            limit: params.limit.unwrap_or(DEFAULT_LINE_LIMIT),
+            max_bytes: params.max_bytes.unwrap_or(DEFAULT_MAX_BYTES),
+            encoding: params.encoding,
+            max_image_dimension: params.max_image_dimension,
+            workspace: self.config.workspace.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
     }
 
-    #[tool(description = "Write content to a file, creating it if it doesn't exist")]
+    #[tool(
+        description = "Write content to a file, creating it if it doesn't exist. Overwriting a file keeps its line endings, byte order mark and final newline"
+    )]
     async fn write_file(
         &self,
         Parameters(params): Parameters<WriteFileParams>,
@@ -43,19 +117,181 @@ impl CommonToolsServer {
         WriteFileTool {
             path: params.path,
             content: params.content,
+            workspace: self.config.workspace.clone(),
         }
         .call_tool()
         .await
         .map_err(|e| McpError::internal_error(e.to_string(), None))
     }
 
-    #[tool(description = "Execute a shell command in a terminal and return the output")]
+    #[tool(
+        description = "Execute a shell command, or a program with arguments without a shell, and return the output"
+    )]
     async fn execute_command(
         &self,
         Parameters(params): Parameters<ExecuteCommandParams>,
+        cancellation_token: CancellationToken,
+        peer: Peer<RoleServer>,
+        meta: Meta,
     ) -> Result<CallToolResult, McpError> {
+        let invocation = Invocation::new(params.command, params.program, params.args, params.shell)
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+
         ExecuteCommandTool {
+            invocation,
+            working_directory: params.working_directory,
+            env: params.env.unwrap_or_default(),
+            env_clear: params.env_clear.unwrap_or(false),
+            stdin: params.stdin,
+            pty: params.pty.unwrap_or(false).then(|| {
+                let default = PtySize::default();
+                PtySize {
+                    rows: params.pty_rows.unwrap_or(default.rows),
+                    cols: params.pty_cols.unwrap_or(default.cols),
+                }
+            }),
+            timeout_ms: params
+                .timeout_ms
+                .or(self.config.default_command_timeout_ms)
+                .filter(|&ms| ms > 0),
+            limits: params
+                .limits
+                .unwrap_or_default()
+                .tightest(&self.config.resource_limits),
+            max_output_bytes: params
+                .max_output_bytes
+                .unwrap_or(self.config.max_command_output_bytes),
+            output_dir: self.output_dir(),
+            background: params.background.unwrap_or(false),
+            cancellation_token,
+            workspace: self.config.workspace.clone(),
+            processes: self.processes.clone(),
+            policy: self.config.command_policy.clone(),
+            peer: Some(peer),
+            progress_token: meta.get_progress_token(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
+    }
+
+    #[tool(description = "Read output a background process has produced since the last call")]
+    async fn process_output(
+        &self,
+        Parameters(params): Parameters<ProcessOutputParams>,
+    ) -> Result<CallToolResult, McpError> {
+        ProcessOutputTool {
+            process_id: params.process_id,
+            processes: self.processes.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
+    }
+
+    #[tool(description = "Write text to the standard input of a background process")]
+    async fn process_write_stdin(
+        &self,
+        Parameters(params): Parameters<ProcessWriteStdinParams>,
+    ) -> Result<CallToolResult, McpError> {
+        ProcessWriteStdinTool {
+            process_id: params.process_id,
+            input: params.input,
+            close_stdin: params.close_stdin.unwrap_or(false),
+            processes: self.processes.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
+    }
+
+    #[tool(description = "Kill a background process and everything it spawned")]
+    async fn process_kill(
+        &self,
+        Parameters(params): Parameters<ProcessKillParams>,
+    ) -> Result<CallToolResult, McpError> {
+        ProcessKillTool {
+            process_id: params.process_id,
+            processes: self.processes.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
+    }
+
+    #[tool(description = "List background processes started in this session")]
+    async fn process_list(
+        &self,
+        Parameters(_params): Parameters<ProcessListParams>,
+    ) -> Result<CallToolResult, McpError> {
+        ProcessListTool {
+            processes: self.processes.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
+    }
+
+    #[tool(
+        description = "Start a persistent shell session in which the working directory, environment variables and sourced scripts carry over between commands"
+    )]
+    async fn shell_session_create(
+        &self,
+        Parameters(params): Parameters<ShellSessionCreateParams>,
+    ) -> Result<CallToolResult, McpError> {
+        ShellSessionCreateTool {
+            shell: params.shell.unwrap_or(Shell::Bash),
+            working_directory: params.working_directory,
+            env: params.env.unwrap_or_default(),
+            limits: self.config.resource_limits,
+            workspace: self.config.workspace.clone(),
+            sessions: self.shell_sessions.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
+    }
+
+    #[tool(
+        description = "Run a command in a shell session and return its output, exit code and the session's working directory"
+    )]
+    async fn shell_session_run(
+        &self,
+        Parameters(params): Parameters<ShellSessionRunParams>,
+        cancellation_token: CancellationToken,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let timeout_ms = params
+            .timeout_ms
+            .or(self.config.default_command_timeout_ms)
+            .filter(|&ms| ms > 0);
+
+        ShellSessionRunTool {
+            session_id: params.session_id,
             command: params.command,
+            timeout_ms: self.config.resource_limits.wall_timeout_ms(timeout_ms),
+            max_output_bytes: params
+                .max_output_bytes
+                .unwrap_or(self.config.max_command_output_bytes),
+            output_dir: self.output_dir(),
+            cancellation_token,
+            sessions: self.shell_sessions.clone(),
+            policy: self.config.command_policy.clone(),
+            peer: Some(peer),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
+    }
+
+    #[tool(description = "Close a shell session, killing anything still running in it")]
+    async fn shell_session_close(
+        &self,
+        Parameters(params): Parameters<ShellSessionCloseParams>,
+    ) -> Result<CallToolResult, McpError> {
+        ShellSessionCloseTool {
+            session_id: params.session_id,
+            sessions: self.shell_sessions.clone(),
         }
         .call_tool()
         .await
@@ -73,6 +309,7 @@ impl CommonToolsServer {
             offset: params.offset.unwrap_or(0),
             case_sensitive: params.case_sensitive.unwrap_or(false),
             working_directory: params.working_directory,
+            workspace: self.config.workspace.clone(),
         }
         .call_tool()
         .await
@@ -84,10 +321,13 @@ impl CommonToolsServer {
         &self,
         Parameters(params): Parameters<ListDirectoryParams>,
     ) -> Result<CallToolResult, McpError> {
-        ListDirectoryTool { path: params.path }
-            .call_tool()
-            .await
-            .map_err(|e| McpError::internal_error(e.to_string(), None))
+        ListDirectoryTool {
+            path: params.path,
+            workspace: self.config.workspace.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
     }
 
     #[tool(description = "Create a new directory at the specified path")]
@@ -95,10 +335,13 @@ impl CommonToolsServer {
         &self,
         Parameters(params): Parameters<CreateDirectoryParams>,
     ) -> Result<CallToolResult, McpError> {
-        CreateDirectoryTool { path: params.path }
-            .call_tool()
-            .await
-            .map_err(|e| McpError::internal_error(e.to_string(), None))
+        CreateDirectoryTool {
+            path: params.path,
+            workspace: self.config.workspace.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
     }
 
     #[tool(description = "Copy a file or directory to a new location")]
@@ -109,6 +352,7 @@ impl CommonToolsServer {
         CopyPathTool {
             source_path: params.source_path,
             destination_path: params.destination_path,
+            workspace: self.config.workspace.clone(),
         }
         .call_tool()
         .await
@@ -123,6 +367,7 @@ impl CommonToolsServer {
         MovePathTool {
             source_path: params.source_path,
             destination_path: params.destination_path,
+            workspace: self.config.workspace.clone(),
         }
         .call_tool()
         .await
@@ -142,21 +387,47 @@ impl CommonToolsServer {
         .map_err(|e| McpError::internal_error(e.to_string(), None))
     }
 
-    #[tool(description = "Apply search and replace edits to a file")]
+    #[tool(
+        description = "Apply search and replace edits to one or more files and return the resulting diff, or only preview it with dry_run; if any edit fails, no file is changed"
+    )]
     async fn search_replace_edit(
         &self,
         Parameters(params): Parameters<SearchReplaceEditParams>,
     ) -> Result<CallToolResult, McpError> {
+        let files = FileEdits::collect(params.path, params.edits, params.files)
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+
         SearchReplaceEditTool {
+            files,
+            dry_run: params.dry_run.unwrap_or(false),
+            workspace: self.config.workspace.clone(),
+        }
+        .call_tool()
+        .await
+        .map_err(|e| McpError::internal_error(e.to_string(), None))
+    }
+
+    #[tool(
+        description = "Insert, replace or delete lines by line number, optionally checking a hash of each range so stale edits are rejected"
+    )]
+    async fn edit_lines(
+        &self,
+        Parameters(params): Parameters<EditLinesParams>,
+    ) -> Result<CallToolResult, McpError> {
+        EditLinesTool {
             path: params.path,
-            edits: params.edits,
+            operations: params.operations,
+            dry_run: params.dry_run.unwrap_or(false),
+            workspace: self.config.workspace.clone(),
         }
         .call_tool()
         .await
         .map_err(|e| McpError::internal_error(e.to_string(), None))
     }
 
-    #[tool(description = "Apply a unified diff patch to a file")]
+    #[tool(
+        description = "Apply a unified diff patch to one or more files, all together or not at all, and return the resulting diff, or only preview it with dry_run. Hunks whose line numbers have drifted are placed where their context matches, and the offset and fuzz of each is reported"
+    )]
     async fn apply_patch(
         &self,
         Parameters(params): Parameters<ApplyPatchParams>,
@@ -164,6 +435,11 @@ impl CommonToolsServer {
         ApplyPatchTool {
             path: params.path,
             patch: params.patch,
+            directory: params.directory,
+            strip: params.strip,
+            dry_run: params.dry_run.unwrap_or(false),
+            fuzz: params.fuzz.unwrap_or(DEFAULT_FUZZ),
+            workspace: self.config.workspace.clone(),
         }
         .call_tool()
         .await
@@ -205,3 +481,13 @@ impl ServerHandler for CommonToolsServer {
         }
     }
 }
+
+/// Creates the directory truncated command output is spilled to, and a
+/// workspace that can read it back.
+fn create_output_dir(workspace: &Workspace) -> Result<(TempDir, Workspace), ToolError> {
+    let dir = tempfile::Builder::new()
+        .prefix("common-tools-output-")
+        .tempdir()?;
+    let workspace = workspace.with_read_only_root(dir.path())?;
+    Ok((dir, workspace))
+}
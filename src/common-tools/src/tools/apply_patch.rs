@@ -1,177 +1,650 @@
 use rmcp::model::{CallToolResult, Content};
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
 use tokio::fs;
 
-use super::{resolve_path, ToolError};
+use super::atomic_write::{apply_changes, FileChange};
+use super::diff::{diff_name, render_diffs, FileDiff, MAX_DIFF_PREVIEW_BYTES};
+use super::edit_diagnostics::normalize_whitespace;
+use super::patch_set::parse_patch_set;
+use super::text_document::TextDocument;
+use super::{ToolError, Workspace};
+
+/// How many outer context lines a hunk may lose at each end when it doesn't
+/// match as written, as with `patch --fuzz`.
+pub const DEFAULT_FUZZ: usize = 2;
 
 pub struct ApplyPatchTool {
-    pub path: String,
+    /// The file to patch, in place of any paths the patch names. Needed for
+    /// hunks that come without `---`/`+++` headers.
+    pub path: Option<String>,
     pub patch: String,
+    /// Directory the paths in the patch are relative to.
+    pub directory: Option<String>,
+    /// Leading components to remove from the paths in the patch, as with
+    /// `patch -p`. By default only git's `a/` and `b/` prefixes are removed.
+    pub strip: Option<usize>,
+    /// Report the diff the patch would produce without writing anything.
+    pub dry_run: bool,
+    /// Most context lines ignored at either end of a hunk to make it match.
+    pub fuzz: usize,
+    pub workspace: Workspace,
+}
+
+/// What the patch does to one file, for the report.
+struct PatchedFile {
+    /// Where the file ends up, or where it was if the patch deletes it.
+    path: PathBuf,
+    renamed_from: Option<PathBuf>,
+    created: bool,
+    deleted: bool,
+    new_mode: Option<u32>,
+    diff: FileDiff,
+    placements: Vec<Placement>,
+}
+
+impl PatchedFile {
+    fn summary(&self) -> String {
+        let mut notes = Vec::new();
+        if self.created {
+            notes.push("created".to_string());
+        }
+        if self.deleted {
+            notes.push("deleted".to_string());
+        }
+        if let Some(from) = &self.renamed_from {
+            notes.push(format!("renamed from {}", from.display()));
+        }
+        if let Some(mode) = self.new_mode.filter(|_| !self.created) {
+            notes.push(format!("mode {:o}", mode));
+        }
+        notes.push(self.diff.stat());
+        format!("{} ({})", self.path.display(), notes.join(", "))
+    }
 }
 
 impl ApplyPatchTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
-        let abs_path = resolve_path(&self.path, None);
+        let normalized_patch = self.patch.replace("\r\n", "\n").replace('\r', "\n");
+        let patch_lines: Vec<&str> = normalized_patch.lines().collect();
 
-        if !abs_path.exists() {
-            return Err(ToolError::FileNotFound(abs_path.display().to_string()));
+        // An explicit path stands in for whatever the patch calls the file,
+        // so there is nothing to strip from those names.
+        let strip = if self.path.is_some() {
+            Some(0)
+        } else {
+            self.strip
+        };
+        let mut file_patches =
+            parse_patch_set(&patch_lines, strip).map_err(ToolError::InvalidArgument)?;
+        if file_patches.is_empty() {
+            return Err(ToolError::InvalidArgument(
+                "The patch contains no hunks".to_string(),
+            ));
         }
 
-        let original_content = fs::read_to_string(&abs_path).await.map_err(ToolError::Io)?;
-        let original_lines: Vec<&str> = original_content.lines().collect();
+        if let Some(path) = &self.path {
+            if file_patches.len() > 1 {
+                return Err(ToolError::InvalidArgument(format!(
+                    "The patch changes {} files; leave out path to apply it to the files it names",
+                    file_patches.len()
+                )));
+            }
+            let file = &mut file_patches[0];
+            let unnamed = file.old_path.is_none() && file.new_path.is_none();
+            if unnamed || file.old_path.is_some() {
+                file.old_path = Some(path.clone());
+            }
+            if unnamed || file.new_path.is_some() {
+                file.new_path = Some(path.clone());
+            }
+            file.rename = false;
+        }
 
-        let normalized_patch = self.patch.replace("\r\n", "\n").replace('\r', "\n");
+        let base = self
+            .directory
+            .as_deref()
+            .map(|dir| self.workspace.resolve_write(dir))
+            .transpose()?;
+        let resolve = |name: &str| match &base {
+            Some(base) => self.workspace.resolve_write(base.join(name)),
+            None => self.workspace.resolve_write(name),
+        };
 
-        let patched_lines =
-            apply_unified_diff(&original_lines, &normalized_patch).ok_or_else(|| {
-                ToolError::Other("Failed to apply patch - hunks did not match".to_string())
-            })?;
+        let mut patched = Vec::with_capacity(file_patches.len());
+        let mut changes = Vec::new();
+        let mut touched = HashSet::new();
+        let mut failures = Vec::new();
 
-        let patched_content = patched_lines.join("\n");
-        let final_content = if original_content.ends_with('\n') {
-            format!("{}\n", patched_content)
-        } else {
-            patched_content
+        for file in &file_patches {
+            if file.old_path.is_none() && file.new_path.is_none() {
+                return Err(ToolError::InvalidArgument(
+                    "The patch doesn't name the file it changes; pass path".to_string(),
+                ));
+            }
+            let old_path = file.old_path.as_deref().map(&resolve).transpose()?;
+            let new_path = file.new_path.as_deref().map(&resolve).transpose()?;
+
+            // Plain diffs may name the file differently on each side, as in
+            // `--- file.orig` and `+++ file`; patch whichever one exists.
+            let (old_path, new_path) = match (old_path, new_path) {
+                (Some(old), Some(new)) if !file.rename && old != new => {
+                    let target = if new.exists() { new } else { old };
+                    (Some(target.clone()), Some(target))
+                }
+                paths => paths,
+            };
+
+            let paths: BTreeSet<&PathBuf> = old_path.iter().chain(&new_path).collect();
+            for path in paths {
+                if !touched.insert(path.clone()) {
+                    return Err(ToolError::InvalidArgument(format!(
+                        "The patch changes {} more than once",
+                        path.display()
+                    )));
+                }
+            }
+            if let Some(old) = &old_path {
+                if !old.exists() {
+                    return Err(ToolError::FileNotFound(old.display().to_string()));
+                }
+            }
+            if let Some(new) = new_path
+                .as_ref()
+                .filter(|new| old_path.as_ref() != Some(*new))
+            {
+                if new.exists() {
+                    return Err(ToolError::InvalidArgument(format!(
+                        "{} already exists",
+                        new.display()
+                    )));
+                }
+            }
+
+            let display_path = new_path
+                .clone()
+                .or_else(|| old_path.clone())
+                .unwrap_or_default();
+            let (content, diff, placements) = if file.hunk_lines.is_empty() {
+                // A rename or mode change alone keeps the bytes as they are.
+                let content = match &old_path {
+                    Some(old) => fs::read(old).await.map_err(ToolError::Io)?,
+                    None => Vec::new(),
+                };
+                (
+                    content,
+                    FileDiff::new(&diff_name(&display_path, &self.workspace), "", ""),
+                    Vec::new(),
+                )
+            } else {
+                let document = match &old_path {
+                    Some(old) => TextDocument::read(old).await?,
+                    None => TextDocument::parse(""),
+                };
+                let result = apply_unified_diff(
+                    &document.lines(),
+                    document.final_newline(),
+                    &file.hunk_lines,
+                    self.fuzz,
+                );
+                let Applied {
+                    lines: patched_lines,
+                    final_newline,
+                    placements,
+                } = match result {
+                    Ok(applied) => applied,
+                    Err(e) => {
+                        failures.push(format!("{}: {}", display_path.display(), e));
+                        continue;
+                    }
+                };
+                let patched_content = document.join_lines_ending(&patched_lines, final_newline);
+                if new_path.is_none() && !patched_content.is_empty() {
+                    failures.push(format!(
+                        "{}: the patch deletes the file but leaves {} lines in it",
+                        display_path.display(),
+                        patched_lines.len()
+                    ));
+                    continue;
+                }
+                let diff = FileDiff::new(
+                    &diff_name(&display_path, &self.workspace),
+                    &document.text,
+                    &patched_content,
+                );
+                (
+                    document.render(&patched_content).into_bytes(),
+                    diff,
+                    placements,
+                )
+            };
+
+            if let Some(new) = &new_path {
+                changes.push(FileChange::Write {
+                    path: new.clone(),
+                    content,
+                    mode: file.new_mode,
+                });
+            }
+            if let Some(old) = old_path
+                .as_ref()
+                .filter(|old| new_path.as_ref() != Some(*old))
+            {
+                changes.push(FileChange::Delete { path: old.clone() });
+            }
+
+            patched.push(PatchedFile {
+                path: display_path,
+                renamed_from: old_path.filter(|_| file.rename),
+                created: file.old_path.is_none(),
+                deleted: new_path.is_none(),
+                new_mode: file.new_mode,
+                diff,
+                placements,
+            });
+        }
+
+        if !failures.is_empty() {
+            return Err(ToolError::Other(format!(
+                "Failed to apply patch, nothing was changed:\n{}",
+                failures.join("\n")
+            )));
+        }
+
+        let summary = match patched.as_slice() {
+            [file] => {
+                let mut summary = file.summary();
+                for placement in &file.placements {
+                    summary.push_str(&format!("\n{}", placement.describe()));
+                }
+                summary
+            }
+            files => {
+                let mut summary = format!("{} files:", files.len());
+                for file in files {
+                    summary.push_str(&format!("\n  {}", file.summary()));
+                    for placement in &file.placements {
+                        summary.push_str(&format!("\n    {}", placement.describe()));
+                    }
+                }
+                summary
+            }
         };
+        let diffs = patched.iter().map(|file| &file.diff);
+
+        if self.dry_run {
+            let message = format!(
+                "Dry run, nothing was written. The patch would change {}\n\n{}",
+                summary,
+                render_diffs(diffs, None)
+            );
+            return Ok(CallToolResult::success(vec![Content::text(message)]));
+        }
 
-        fs::write(&abs_path, &final_content)
+        tokio::task::spawn_blocking(move || apply_changes(changes))
             .await
-            .map_err(ToolError::Io)?;
+            .map_err(|e| ToolError::Other(e.to_string()))??;
 
-        let message = format!("Successfully applied patch to {}", abs_path.display());
+        let message = format!(
+            "Successfully applied patch to {}\n\n{}",
+            summary,
+            render_diffs(diffs, Some(MAX_DIFF_PREVIEW_BYTES))
+        );
         Ok(CallToolResult::success(vec![Content::text(message)]))
     }
 }
 
-#[allow(dead_code)]
 struct HunkData {
     old_start: usize,
-    old_count: usize,
-    new_start: usize,
-    new_count: usize,
     context_and_removed: Vec<String>,
     new_lines: Vec<String>,
+    /// Context lines before the first change, which fuzz may ignore.
+    leading_context: usize,
+    /// Context lines after the last change.
+    trailing_context: usize,
+    /// Whether a "No newline at end of file" marker follows the last old
+    /// line, and the last new line.
+    old_no_newline: bool,
+    new_no_newline: bool,
 }
 
-fn apply_unified_diff(original_lines: &[&str], patch: &str) -> Option<Vec<String>> {
-    let patch_lines: Vec<&str> = patch.lines().collect();
-    if patch_lines.is_empty() {
-        return None;
+impl HunkData {
+    /// Index in the original file where the header says the old lines start.
+    /// A hunk without old lines inserts after line `old_start`.
+    fn stated_start(&self) -> usize {
+        if self.context_and_removed.is_empty() {
+            self.old_start
+        } else {
+            self.old_start.saturating_sub(1)
+        }
+    }
+}
+
+/// Where a hunk was applied, for the report.
+struct Placement {
+    /// 1-based position of the hunk in the patch.
+    hunk: usize,
+    /// 1-based line of the original file the hunk was applied at.
+    line: usize,
+    /// How far that is from the line the hunk header names.
+    offset: isize,
+    fuzz: usize,
+}
+
+impl Placement {
+    fn describe(&self) -> String {
+        format!(
+            "Hunk #{} applied at line {} (offset {:+} lines, fuzz {})",
+            self.hunk, self.line, self.offset, self.fuzz
+        )
     }
+}
+
+/// A match for a hunk: its old lines, less the context lines fuzz left out,
+/// start at index `start` of the original file.
+struct Located {
+    start: usize,
+    leading: usize,
+    trailing: usize,
+    fuzz: usize,
+}
 
-    let hunks = parse_unified_diff(&patch_lines)?;
+/// The patched file.
+struct Applied {
+    lines: Vec<String>,
+    /// Whether it ends with a newline.
+    final_newline: bool,
+    placements: Vec<Placement>,
+}
+
+/// Applies every hunk to `original_lines` the way GNU patch does. Each hunk is
+/// looked for first where its header says, shifted by the offset the previous
+/// hunk needed, then at growing distances either side. If it matches nowhere,
+/// up to `max_fuzz` context lines are dropped from each end and the search
+/// repeated. Hunks must apply in order and may not overlap.
+///
+/// `final_newline` says whether the original ends with a newline. A hunk
+/// whose old side is marked "No newline at end of file" only applies at the
+/// end of a file without one, and the new side of a hunk that reaches the
+/// end of the file decides whether the result has one.
+fn apply_unified_diff(
+    original_lines: &[&str],
+    mut final_newline: bool,
+    patch_lines: &[&str],
+    max_fuzz: usize,
+) -> Result<Applied, String> {
+    let hunks = parse_unified_diff(patch_lines)?;
     if hunks.is_empty() {
-        return None;
+        return Err("The patch contains no hunks".to_string());
     }
 
-    let mut result: Vec<String> = original_lines.iter().map(|s| s.to_string()).collect();
+    let normalized: Vec<String> = original_lines
+        .iter()
+        .map(|line| normalize_whitespace(line))
+        .collect();
+
+    let mut result = Vec::with_capacity(original_lines.len());
+    let mut placements = Vec::with_capacity(hunks.len());
+    let mut failures = Vec::new();
+    // Lines before `next` have already been copied or replaced.
+    let mut next = 0;
+    let mut last_offset = 0;
+
+    for (i, hunk) in hunks.iter().enumerate() {
+        let Some(found) = locate_hunk(&normalized, hunk, next, last_offset, max_fuzz) else {
+            failures.push(format!(
+                "Hunk #{} did not match near line {} or anywhere after the previous hunk, even \
+                 ignoring {} lines of context at each end. It expects:\n{}",
+                i + 1,
+                hunk.old_start,
+                max_fuzz,
+                hunk.context_and_removed.join("\n")
+            ));
+            continue;
+        };
 
-    for hunk in hunks.iter().rev() {
-        if hunk.old_start == 0 || hunk.old_start > result.len() + 1 {
-            return None;
+        let old_end = hunk.context_and_removed.len() - found.trailing;
+        let new_end = hunk.new_lines.len() - found.trailing;
+        let reaches_end =
+            found.start + old_end - found.leading + found.trailing == original_lines.len();
+        if hunk.old_no_newline && (!reaches_end || final_newline) {
+            failures.push(format!(
+                "Hunk #{} expects the file to end without a newline after line {}, but it \
+                 doesn't",
+                i + 1,
+                found.start + old_end - found.leading + found.trailing
+            ));
+            continue;
         }
-
-        let idx = hunk.old_start - 1;
-        if idx + hunk.old_count > result.len() {
-            return None;
+        result.extend(
+            original_lines[next..found.start]
+                .iter()
+                .map(|s| s.to_string()),
+        );
+        result.extend(hunk.new_lines[found.leading..new_end].iter().cloned());
+        next = found.start + old_end - found.leading;
+        if reaches_end {
+            final_newline = !hunk.new_no_newline;
         }
 
-        let actual_lines: Vec<&str> = result[idx..idx + hunk.old_count]
-            .iter()
-            .map(|s| s.as_str())
-            .collect();
+        let hunk_start = found.start.saturating_sub(found.leading);
+        last_offset = hunk_start as isize - hunk.stated_start() as isize;
+        placements.push(Placement {
+            hunk: i + 1,
+            line: hunk_start + 1,
+            offset: last_offset,
+            fuzz: found.fuzz,
+        });
+    }
 
-        if actual_lines.len() != hunk.context_and_removed.len() {
-            return None;
-        }
+    if !failures.is_empty() {
+        return Err(failures.join("\n"));
+    }
+
+    result.extend(original_lines[next..].iter().map(|s| s.to_string()));
+    Ok(Applied {
+        lines: result,
+        final_newline,
+        placements,
+    })
+}
 
-        for (actual, expected) in actual_lines.iter().zip(hunk.context_and_removed.iter()) {
-            let actual_normalized = actual.split_whitespace().collect::<Vec<_>>().join(" ");
-            let expected_normalized = expected.split_whitespace().collect::<Vec<_>>().join(" ");
+/// Finds where `hunk` applies among the whitespace-normalized `lines`, no
+/// earlier than index `earliest`, preferring the least fuzz and then the
+/// position nearest the one stated.
+fn locate_hunk(
+    lines: &[String],
+    hunk: &HunkData,
+    earliest: usize,
+    last_offset: isize,
+    max_fuzz: usize,
+) -> Option<Located> {
+    let old_lines: Vec<String> = hunk
+        .context_and_removed
+        .iter()
+        .map(|line| normalize_whitespace(line))
+        .collect();
+
+    let mut previous = None;
+    for fuzz in 0..=max_fuzz {
+        let leading = fuzz.min(hunk.leading_context);
+        let trailing = fuzz.min(hunk.trailing_context);
+        // Once all the outer context is gone, more fuzz changes nothing.
+        if previous == Some((leading, trailing)) {
+            break;
+        }
+        previous = Some((leading, trailing));
 
-            if actual_normalized != expected_normalized {
-                return None;
+        let pattern = &old_lines[leading..old_lines.len() - trailing];
+        if lines.len() < earliest + pattern.len() {
+            continue;
+        }
+        let latest = lines.len() - pattern.len();
+
+        let stated = hunk.stated_start() as isize + last_offset + leading as isize;
+        let expected = stated.clamp(earliest as isize, latest as isize) as usize;
+
+        let matches_at = |start: usize| lines[start..start + pattern.len()] == *pattern;
+        let max_distance = (expected - earliest).max(latest - expected);
+        for distance in 0..=max_distance {
+            let after = Some(expected + distance).filter(|&s| s <= latest);
+            let before = expected
+                .checked_sub(distance)
+                .filter(|&s| s >= earliest && distance > 0);
+            if let Some(start) = [after, before]
+                .into_iter()
+                .flatten()
+                .find(|&s| matches_at(s))
+            {
+                return Some(Located {
+                    start,
+                    leading,
+                    trailing,
+                    fuzz,
+                });
             }
         }
-
-        result.splice(idx..idx + hunk.old_count, hunk.new_lines.iter().cloned());
     }
 
-    Some(result)
+    None
+}
+
+/// The line ranges in a hunk's `@@ -old_start,old_count +new_start,new_count @@`
+/// header. A count left out is 1.
+pub(super) struct HunkHeader {
+    pub(super) old_start: usize,
+    pub(super) old_count: usize,
+    pub(super) new_count: usize,
 }
 
-fn parse_unified_diff(patch_lines: &[&str]) -> Option<Vec<HunkData>> {
+pub(super) fn parse_hunk_header(line: &str) -> Result<HunkHeader, String> {
+    let malformed = || format!("Malformed hunk header: {}", line);
+
+    let ranges = line
+        .strip_prefix("@@")
+        .and_then(|rest| rest.split("@@").next())
+        .ok_or_else(malformed)?;
+    let (old_range, new_range) = match ranges.split_whitespace().collect::<Vec<_>>()[..] {
+        [old, new] => (
+            old.strip_prefix('-').ok_or_else(malformed)?,
+            new.strip_prefix('+').ok_or_else(malformed)?,
+        ),
+        _ => return Err(malformed()),
+    };
+
+    let parse_range = |range: &str| -> Result<(usize, usize), String> {
+        let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+        Ok((
+            start.parse().map_err(|_| malformed())?,
+            count.parse().map_err(|_| malformed())?,
+        ))
+    };
+    let (old_start, old_count) = parse_range(old_range)?;
+    let (_, new_count) = parse_range(new_range)?;
+
+    Ok(HunkHeader {
+        old_start,
+        old_count,
+        new_count,
+    })
+}
+
+/// Reads the hunks of a patch. Each hunk is exactly as long as its header
+/// says, as with GNU patch, so anything between hunks, such as the signature
+/// `git format-patch` adds, is skipped rather than taken for changed lines.
+fn parse_unified_diff(patch_lines: &[&str]) -> Result<Vec<HunkData>, String> {
     let mut hunks = Vec::new();
     let mut i = 0;
 
     while i < patch_lines.len() {
         let line = patch_lines[i];
+        i += 1;
+        if !line.starts_with("@@") {
+            continue;
+        }
 
-        if line.starts_with("@@") {
-            let parts: Vec<&str> = line.split("@@").collect();
-            if parts.len() < 2 {
-                i += 1;
-                continue;
-            }
-
-            let ranges: Vec<&str> = parts[1].split_whitespace().collect();
-            if ranges.len() < 2 {
-                i += 1;
-                continue;
-            }
-
-            let old_range: Vec<&str> = ranges[0].trim_start_matches('-').split(',').collect();
-            let new_range: Vec<&str> = ranges[1].trim_start_matches('+').split(',').collect();
-
-            let old_start = old_range[0].parse::<usize>().ok()?;
-            let old_count = if old_range.len() > 1 {
-                old_range[1].parse::<usize>().ok()?
-            } else {
-                1
+        let header = parse_hunk_header(line)?;
+        let (mut old_left, mut new_left) = (header.old_count, header.new_count);
+        let mut context_and_removed = Vec::new();
+        let mut new_lines = Vec::new();
+        // Whether each line is context, to measure the outer context.
+        let mut is_context = Vec::new();
+        let (mut old_no_newline, mut new_no_newline) = (false, false);
+
+        while old_left > 0 || new_left > 0 {
+            let Some(&hunk_line) = patch_lines.get(i) else {
+                return Err(format!(
+                    "The patch ends before the hunk {} does; it is {} old and {} new lines short",
+                    line, old_left, new_left
+                ));
             };
-
-            let new_start = new_range[0].parse::<usize>().ok()?;
-            let new_count = if new_range.len() > 1 {
-                new_range[1].parse::<usize>().ok()?
-            } else {
-                1
+            let doesnt_fit = || {
+                format!(
+                    "The hunk {} doesn't match its header's line counts at: {}",
+                    line, hunk_line
+                )
             };
-
             i += 1;
-            let mut context_and_removed = Vec::new();
-            let mut new_lines = Vec::new();
-
-            while i < patch_lines.len() && !patch_lines[i].starts_with("@@") {
-                let hunk_line = patch_lines[i];
-                if let Some(content) = hunk_line.strip_prefix(' ') {
-                    context_and_removed.push(content.to_string());
-                    new_lines.push(content.to_string());
-                } else if let Some(content) = hunk_line.strip_prefix('-') {
-                    context_and_removed.push(content.to_string());
-                } else if let Some(content) = hunk_line.strip_prefix('+') {
-                    new_lines.push(content.to_string());
-                } else if hunk_line.starts_with('\\') {
-                    // "No newline at end of file" marker - skip
-                } else if hunk_line.trim().is_empty() {
-                    context_and_removed.push(String::new());
-                    new_lines.push(String::new());
-                } else {
-                    break;
-                }
+            // A "No newline at end of file" marker belongs to the line before.
+            let no_newline = patch_lines.get(i).is_some_and(|l| l.starts_with('\\'));
+            if no_newline {
                 i += 1;
             }
 
-            hunks.push(HunkData {
-                old_start,
-                old_count,
-                new_start,
-                new_count,
-                context_and_removed,
-                new_lines,
-            });
-        } else {
-            i += 1;
+            // Editors sometimes strip the space from an empty context line.
+            let context = hunk_line
+                .strip_prefix(' ')
+                .or(hunk_line.trim().is_empty().then_some(""));
+            if let Some(content) = context {
+                if old_left == 0 || new_left == 0 {
+                    return Err(doesnt_fit());
+                }
+                old_left -= 1;
+                new_left -= 1;
+                context_and_removed.push(content.to_string());
+                new_lines.push(content.to_string());
+                is_context.push(true);
+                old_no_newline = no_newline;
+                new_no_newline = no_newline;
+            } else if let Some(content) = hunk_line.strip_prefix('-') {
+                if old_left == 0 {
+                    return Err(doesnt_fit());
+                }
+                old_left -= 1;
+                context_and_removed.push(content.to_string());
+                is_context.push(false);
+                old_no_newline = no_newline;
+            } else if let Some(content) = hunk_line.strip_prefix('+') {
+                if new_left == 0 {
+                    return Err(doesnt_fit());
+                }
+                new_left -= 1;
+                new_lines.push(content.to_string());
+                is_context.push(false);
+                new_no_newline = no_newline;
+            } else if !hunk_line.starts_with('\\') {
+                return Err(doesnt_fit());
+            }
         }
+
+        let leading_context = is_context.iter().take_while(|&&c| c).count();
+        let trailing_context = is_context
+            .iter()
+            .rev()
+            .take_while(|&&c| c)
+            .count()
+            .min(is_context.len() - leading_context);
+
+        hunks.push(HunkData {
+            old_start: header.old_start,
+            context_and_removed,
+            new_lines,
+            leading_context,
+            trailing_context,
+            old_no_newline,
+            new_no_newline,
+        });
     }
 
-    Some(hunks)
+    Ok(hunks)
 }
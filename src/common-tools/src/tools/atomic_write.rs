@@ -0,0 +1,172 @@
+use std::fs::Permissions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+use super::ToolError;
+
+/// One change in a set applied by `apply_changes`.
+pub(crate) enum FileChange {
+    /// Create or replace a file. `mode` sets its Unix permission bits;
+    /// otherwise a replaced file keeps its permissions.
+    Write {
+        path: PathBuf,
+        content: Vec<u8>,
+        mode: Option<u32>,
+    },
+    Delete {
+        path: PathBuf,
+    },
+}
+
+/// What a file held before it was changed, to put it back.
+struct Original {
+    bytes: Vec<u8>,
+    permissions: Permissions,
+}
+
+/// Replaces the contents of several files so that either all of them change
+/// or none do.
+pub(crate) fn write_files(files: &[(PathBuf, String)]) -> Result<(), ToolError> {
+    let changes = files
+        .iter()
+        .map(|(path, content)| FileChange::Write {
+            path: path.clone(),
+            content: content.clone().into_bytes(),
+            mode: None,
+        })
+        .collect();
+    apply_changes(changes)
+}
+
+/// Creates, replaces and deletes files so that either every change happens
+/// or none does.
+///
+/// Every new version is first written to a temporary file next to the file
+/// it replaces, so running out of space or permission problems surface
+/// before anything is touched. The temporary files are then renamed over
+/// their targets and deleted files removed; if one of those steps fails, the
+/// files already changed are restored from their original contents.
+pub(crate) fn apply_changes(changes: Vec<FileChange>) -> Result<(), ToolError> {
+    let mut staged = Vec::with_capacity(changes.len());
+    for change in changes {
+        match change {
+            FileChange::Write {
+                path,
+                content,
+                mode,
+            } => {
+                // Replace what a symlink points to rather than the link itself.
+                let path = std::fs::canonicalize(&path).unwrap_or(path);
+                let original = read_original(&path)?;
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| io_error(parent, e))?;
+                }
+                let permissions = mode
+                    .and_then(mode_permissions)
+                    .or_else(|| original.as_ref().map(|o| o.permissions.clone()));
+                let temp = stage(&path, &content, permissions)?;
+                staged.push((path, original, Some(temp)));
+            }
+            FileChange::Delete { path } => {
+                let original = read_original(&path)?;
+                if original.is_none() {
+                    return Err(ToolError::FileNotFound(path.display().to_string()));
+                }
+                staged.push((path, original, None));
+            }
+        }
+    }
+
+    let mut changed = Vec::with_capacity(staged.len());
+    for (path, original, temp) in staged {
+        let result = match temp {
+            Some(temp) => temp.persist(&path).map(|_| ()).map_err(|e| e.error),
+            None => std::fs::remove_file(&path),
+        };
+        if let Err(e) = result {
+            restore(&changed);
+            return Err(io_error(&path, e));
+        }
+        changed.push((path, original));
+    }
+
+    Ok(())
+}
+
+fn read_original(path: &Path) -> Result<Option<Original>, ToolError> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(io_error(path, e)),
+    };
+    let permissions = std::fs::metadata(path)
+        .map_err(|e| io_error(path, e))?
+        .permissions();
+    Ok(Some(Original { bytes, permissions }))
+}
+
+/// Writes `content` to a temporary file in the same directory as `path`,
+/// with the given permissions, ready to be renamed over it.
+fn stage(
+    path: &Path,
+    content: &[u8],
+    permissions: Option<Permissions>,
+) -> Result<NamedTempFile, ToolError> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let mut temp = tempfile::Builder::new()
+        .prefix(".common-tools-")
+        .tempfile_in(dir)
+        .map_err(|e| io_error(path, e))?;
+    temp.write_all(content).map_err(|e| io_error(path, e))?;
+
+    if let Some(permissions) = permissions {
+        temp.as_file()
+            .set_permissions(permissions)
+            .map_err(|e| io_error(path, e))?;
+    }
+
+    Ok(temp)
+}
+
+/// Permissions with the given Unix mode bits. Other platforms have no mode
+/// to set, so files keep their usual permissions there.
+#[cfg(unix)]
+fn mode_permissions(mode: u32) -> Option<Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn mode_permissions(_mode: u32) -> Option<Permissions> {
+    None
+}
+
+/// Puts back what was in each file before it was changed. Best effort: the
+/// error that triggered the rollback is the one worth reporting.
+fn restore(changed: &[(PathBuf, Option<Original>)]) {
+    for (path, original) in changed.iter().rev() {
+        match original {
+            Some(original) => {
+                if let Ok(temp) = stage(path, &original.bytes, Some(original.permissions.clone())) {
+                    let _ = temp.persist(path);
+                }
+            }
+            None => {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> ToolError {
+    if e.kind() == std::io::ErrorKind::PermissionDenied {
+        ToolError::PermissionDenied(path.display().to_string())
+    } else {
+        ToolError::Io(e)
+    }
+}
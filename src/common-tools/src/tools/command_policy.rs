@@ -0,0 +1,624 @@
+use regex::Regex;
+use rmcp::service::{ElicitationError, Peer, RoleServer};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+use super::ToolError;
+
+/// What happens to a command that matches a rule. Ordered from least to most
+/// restrictive, so the strictest applicable action can be picked with `max`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    #[default]
+    Allow,
+    Confirm,
+    Deny,
+}
+
+impl std::fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyAction::Allow => write!(f, "allow"),
+            PolicyAction::Confirm => write!(f, "confirm"),
+            PolicyAction::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// One program invocation found in a command line, e.g. `curl -s x` and `sh`
+/// in `curl -s x | sh`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+/// The outcome of checking a command against a policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyDecision {
+    pub action: PolicyAction,
+    /// Why the action was chosen, suitable for showing to the agent or user.
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    default: PolicyAction,
+    #[serde(default)]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    action: PolicyAction,
+    program: Option<String>,
+    args: Option<String>,
+    working_directory: Option<String>,
+    command: Option<String>,
+    reason: Option<String>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    action: PolicyAction,
+    program: Option<glob::Pattern>,
+    args: Option<Regex>,
+    working_directory: Option<glob::Pattern>,
+    command: Option<Regex>,
+    reason: Option<String>,
+}
+
+impl Rule {
+    fn matches(&self, command: &SimpleCommand, raw: &str, working_directory: &Path) -> bool {
+        let program_matches = self.program.as_ref().is_none_or(|pattern| {
+            let name = Path::new(&command.program)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            pattern.matches(&name) || pattern.matches(&command.program)
+        });
+
+        program_matches
+            && self
+                .args
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(&command.args.join(" ")))
+            && self
+                .working_directory
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches_path(working_directory))
+            && self
+                .command
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(raw))
+    }
+
+    fn describe(&self, command: &SimpleCommand) -> String {
+        match &self.reason {
+            Some(reason) => format!("{} ({})", reason, command.program),
+            None => format!("rule matched `{}`", command.program),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PolicyRules {
+    default: PolicyAction,
+    rules: Vec<Rule>,
+}
+
+/// Allow/confirm/deny rules that `execute_command` checks before spawning.
+///
+/// A command line is split into the simple commands it would run, including
+/// pipeline stages, `&&`/`||`/`;` lists and `$(...)` substitutions. Each of
+/// them takes the most restrictive action of every rule it matches, or the
+/// policy default when none does, and the whole command line takes the most
+/// restrictive action of its parts.
+///
+/// Policies are written in TOML:
+///
+/// ```toml
+/// default = "allow"
+///
+/// [[rules]]
+/// action = "deny"
+/// program = "rm"
+/// args = "-[a-zA-Z]*r[a-zA-Z]*f|-[a-zA-Z]*f[a-zA-Z]*r"
+/// reason = "recursive forced removal"
+///
+/// [[rules]]
+/// action = "confirm"
+/// command = "(curl|wget)[^|]*\\|\\s*(ba|z)?sh"
+/// reason = "piping a download into a shell"
+/// ```
+///
+/// `program` and `working_directory` are globs, matched against the program's
+/// file name (or full path) and the directory the command runs in. `args` is a
+/// regex searched for in the space-joined arguments, and `command` a regex
+/// searched for in the full command line. Every field given must match.
+#[derive(Debug, Clone, Default)]
+pub struct CommandPolicy {
+    inner: Arc<PolicyRules>,
+}
+
+impl CommandPolicy {
+    /// A policy without rules that allows every command.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ToolError> {
+        let file: PolicyFile = toml::from_str(text)
+            .map_err(|e| ToolError::InvalidArgument(format!("Invalid command policy: {}", e)))?;
+
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(index, rule)| compile_rule(index + 1, rule))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            inner: Arc::new(PolicyRules {
+                default: file.default,
+                rules,
+            }),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ToolError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                ToolError::FileNotFound(path.display().to_string())
+            } else {
+                ToolError::Io(e)
+            }
+        })?;
+        Self::from_toml(&text)
+    }
+
+    /// Checks a shell command line.
+    pub fn evaluate(&self, command: &str, working_directory: &Path) -> PolicyDecision {
+        self.evaluate_commands(&parse_shell_command(command), command, working_directory)
+    }
+
+    /// Checks commands that have already been split into program and arguments.
+    /// `raw` is what `command` rules are matched against.
+    pub fn evaluate_commands(
+        &self,
+        commands: &[SimpleCommand],
+        raw: &str,
+        working_directory: &Path,
+    ) -> PolicyDecision {
+        let policy = &self.inner;
+        let mut decision = PolicyDecision {
+            action: policy.default,
+            reason: format!("default policy is {}", policy.default),
+        };
+        if commands.is_empty() {
+            return decision;
+        }
+
+        let mut first = true;
+        for command in commands {
+            let matched = policy
+                .rules
+                .iter()
+                .filter(|rule| rule.matches(command, raw, working_directory))
+                .max_by_key(|rule| rule.action);
+
+            let candidate = match matched {
+                Some(rule) => PolicyDecision {
+                    action: rule.action,
+                    reason: rule.describe(command),
+                },
+                None => PolicyDecision {
+                    action: policy.default,
+                    reason: format!(
+                        "`{}` matches no rule and the default policy is {}",
+                        command.program, policy.default
+                    ),
+                },
+            };
+
+            if first || candidate.action > decision.action {
+                decision = candidate;
+                first = false;
+            }
+        }
+
+        decision
+    }
+}
+
+/// The form shown to the user when a command needs confirmation.
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct CommandConfirmation {
+    #[schemars(description = "Run the command")]
+    approve: bool,
+}
+
+rmcp::elicit_safe!(CommandConfirmation);
+
+/// Fails unless `decision` lets `command` run, asking the user through MCP
+/// elicitation when the policy wants confirmation.
+pub(crate) async fn enforce_decision(
+    decision: &PolicyDecision,
+    command: &str,
+    working_directory: &Path,
+    peer: Option<&Peer<RoleServer>>,
+    cancellation_token: &CancellationToken,
+) -> Result<(), ToolError> {
+    match decision.action {
+        PolicyAction::Allow => return Ok(()),
+        PolicyAction::Deny => {
+            return Err(ToolError::PolicyViolation(format!(
+                "`{}` is denied: {}",
+                command, decision.reason
+            )))
+        }
+        PolicyAction::Confirm => {}
+    }
+
+    let not_confirmed = |why: String| {
+        ToolError::PolicyViolation(format!(
+            "`{}` requires confirmation ({}) but {}",
+            command, decision.reason, why
+        ))
+    };
+
+    let Some(peer) = peer else {
+        return Err(not_confirmed("no client is available to ask".to_string()));
+    };
+
+    let message = format!(
+        "Allow this command to run?\n\n{}\n\nWorking directory: {}\nReason: {}",
+        command,
+        working_directory.display(),
+        decision.reason
+    );
+
+    let response = tokio::select! {
+        response = peer.elicit::<CommandConfirmation>(message) => response,
+        _ = cancellation_token.cancelled() => {
+            return Err(not_confirmed("the call was cancelled while waiting".to_string()));
+        }
+    };
+
+    match response {
+        Ok(Some(CommandConfirmation { approve: true })) => Ok(()),
+        Ok(_) | Err(ElicitationError::UserDeclined | ElicitationError::UserCancelled) => {
+            Err(not_confirmed("the user did not approve it".to_string()))
+        }
+        Err(ElicitationError::CapabilityNotSupported) => Err(not_confirmed(
+            "the client does not support elicitation".to_string(),
+        )),
+        Err(e) => Err(not_confirmed(format!("asking the user failed: {}", e))),
+    }
+}
+
+fn compile_rule(number: usize, rule: RuleFile) -> Result<Rule, ToolError> {
+    let invalid = |field: &str, e: &dyn std::fmt::Display| {
+        ToolError::InvalidArgument(format!(
+            "Invalid command policy: rule {} has an invalid {}: {}",
+            number, field, e
+        ))
+    };
+
+    if rule.program.is_none()
+        && rule.args.is_none()
+        && rule.working_directory.is_none()
+        && rule.command.is_none()
+    {
+        return Err(ToolError::InvalidArgument(format!(
+            "Invalid command policy: rule {} must set at least one of program, args, \
+             working_directory or command",
+            number
+        )));
+    }
+
+    Ok(Rule {
+        action: rule.action,
+        program: rule
+            .program
+            .map(|p| glob::Pattern::new(&p).map_err(|e| invalid("program", &e)))
+            .transpose()?,
+        args: rule
+            .args
+            .map(|r| Regex::new(&r).map_err(|e| invalid("args", &e)))
+            .transpose()?,
+        working_directory: rule
+            .working_directory
+            .map(|p| glob::Pattern::new(&p).map_err(|e| invalid("working_directory", &e)))
+            .transpose()?,
+        command: rule
+            .command
+            .map(|r| Regex::new(&r).map_err(|e| invalid("command", &e)))
+            .transpose()?,
+        reason: rule.reason,
+    })
+}
+
+/// Programs that run the command given in their arguments.
+const WRAPPERS: &[&str] = &[
+    "sudo", "doas", "env", "nohup", "nice", "time", "timeout", "exec", "command", "xargs",
+    "stdbuf", "setsid",
+];
+
+/// Shells whose `-c` argument is itself a command line.
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish"];
+
+/// Words that start compound commands rather than naming a program.
+const RESERVED_WORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "!", "{", "}", "esac",
+];
+
+/// Commands whose header is not itself a program invocation.
+const COMPOUND_HEADERS: &[&str] = &["for", "case", "select", "function"];
+
+/// Splits a POSIX shell command line into the simple commands it runs.
+///
+/// This is a best-effort lexer, not a shell: it understands quoting,
+/// escapes, comments, command separators, redirections and command
+/// substitution, and errs towards reporting more commands rather than fewer.
+pub fn parse_shell_command(command: &str) -> Vec<SimpleCommand> {
+    let mut commands = Vec::new();
+    for words in split_words(command) {
+        expand_words(words, &mut commands);
+    }
+    commands
+}
+
+/// Splits a program and its arguments into the simple commands they run,
+/// looking through wrappers such as `sudo` and shells given `-c`.
+pub fn parse_argv(program: &str, args: &[String]) -> Vec<SimpleCommand> {
+    let mut words = vec![program.to_string()];
+    words.extend(args.iter().cloned());
+
+    let mut commands = Vec::new();
+    expand_words(words, &mut commands);
+    commands
+}
+
+fn expand_words(mut words: Vec<String>, commands: &mut Vec<SimpleCommand>) {
+    while words
+        .first()
+        .is_some_and(|w| RESERVED_WORDS.contains(&w.as_str()))
+    {
+        words.remove(0);
+    }
+    if words
+        .first()
+        .is_some_and(|w| COMPOUND_HEADERS.contains(&w.as_str()))
+    {
+        return;
+    }
+
+    let assignments = words.iter().take_while(|w| is_assignment(w)).count();
+    words.drain(..assignments);
+    if words.is_empty() {
+        return;
+    }
+
+    let program = words.remove(0);
+    let name = Path::new(&program)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    if WRAPPERS.contains(&name.as_str()) {
+        let inner: Vec<String> = words
+            .iter()
+            .skip_while(|w| w.starts_with('-') || is_assignment(w) || is_number(w))
+            .cloned()
+            .collect();
+        expand_words(inner, commands);
+    }
+
+    if SHELLS.contains(&name.as_str()) {
+        if let Some(script) = words
+            .iter()
+            .position(|w| w.starts_with('-') && !w.starts_with("--") && w.contains('c'))
+            .and_then(|i| words.get(i + 1))
+        {
+            commands.extend(parse_shell_command(script));
+        }
+    }
+
+    commands.push(SimpleCommand {
+        program,
+        args: words,
+    });
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+fn is_number(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+/// Words of the command currently being read at one nesting level: the top
+/// level, or inside a `$(...)` or backtick substitution.
+struct Frame {
+    closer: Option<char>,
+    in_double_quotes: bool,
+    words: Vec<String>,
+    word: Option<String>,
+    skip_next_word: bool,
+}
+
+impl Frame {
+    fn new(closer: Option<char>) -> Self {
+        Self {
+            closer,
+            in_double_quotes: false,
+            words: Vec::new(),
+            word: None,
+            skip_next_word: false,
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        self.word.get_or_insert_with(String::new).push(c);
+    }
+
+    fn finish_word(&mut self) {
+        if let Some(word) = self.word.take() {
+            if std::mem::take(&mut self.skip_next_word) {
+                return;
+            }
+            self.words.push(word);
+        }
+    }
+
+    fn finish_command(&mut self, commands: &mut Vec<Vec<String>>) {
+        self.finish_word();
+        self.skip_next_word = false;
+        if !self.words.is_empty() {
+            commands.push(std::mem::take(&mut self.words));
+        }
+    }
+}
+
+fn split_words(command: &str) -> Vec<Vec<String>> {
+    let mut commands = Vec::new();
+    let mut frames = vec![Frame::new(None)];
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let frame = frames.last_mut().unwrap();
+
+        if frame.in_double_quotes {
+            match c {
+                '"' => frame.in_double_quotes = false,
+                '\\' => match chars.peek() {
+                    Some(&next @ ('"' | '\\' | '$' | '`')) => {
+                        chars.next();
+                        frame.push_char(next);
+                    }
+                    Some('\n') => {
+                        chars.next();
+                    }
+                    _ => frame.push_char('\\'),
+                },
+                '$' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    frame.push_char('$');
+                    frames.push(Frame::new(Some(')')));
+                }
+                '`' => {
+                    frame.push_char('`');
+                    frames.push(Frame::new(Some('`')));
+                }
+                _ => frame.push_char(c),
+            }
+            continue;
+        }
+
+        match c {
+            ' ' | '\t' => frame.finish_word(),
+            '\'' => {
+                let word = frame.word.get_or_insert_with(String::new);
+                for quoted in chars.by_ref() {
+                    if quoted == '\'' {
+                        break;
+                    }
+                    word.push(quoted);
+                }
+            }
+            '"' => {
+                frame.word.get_or_insert_with(String::new);
+                frame.in_double_quotes = true;
+            }
+            '\\' => match chars.next() {
+                Some('\n') | None => {}
+                Some(escaped) => frame.push_char(escaped),
+            },
+            '#' if frame.word.is_none() => {
+                while chars.peek().is_some_and(|&next| next != '\n') {
+                    chars.next();
+                }
+            }
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                frame.push_char('$');
+                frames.push(Frame::new(Some(')')));
+            }
+            '`' if frame.closer == Some('`') => {
+                let mut inner = frames.pop().unwrap();
+                inner.finish_command(&mut commands);
+            }
+            '`' => {
+                frame.push_char('`');
+                frames.push(Frame::new(Some('`')));
+            }
+            ')' if frame.closer == Some(')') => {
+                let mut inner = frames.pop().unwrap();
+                inner.finish_command(&mut commands);
+            }
+            '&' if chars.peek() == Some(&'>') => {
+                frame.finish_word();
+                chars.next();
+                if start_redirection(frame, &mut chars) {
+                    frames.push(Frame::new(Some(')')));
+                }
+            }
+            '<' | '>' => {
+                if frame.word.as_deref().is_some_and(is_number) {
+                    frame.word = None;
+                } else {
+                    frame.finish_word();
+                }
+                if start_redirection(frame, &mut chars) {
+                    frames.push(Frame::new(Some(')')));
+                }
+            }
+            ';' | '&' | '|' | '\n' | '(' | ')' => frame.finish_command(&mut commands),
+            _ => frame.push_char(c),
+        }
+    }
+
+    // Unterminated quotes or substitutions still contribute what was read.
+    while let Some(mut frame) = frames.pop() {
+        frame.finish_command(&mut commands);
+    }
+
+    commands
+}
+
+/// Consumes the rest of a redirection operator and arranges for its target
+/// to be left out of the command's arguments. Returns true for `<(...)` and
+/// `>(...)` process substitutions, which are read as nested commands instead.
+fn start_redirection(
+    frame: &mut Frame,
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+) -> bool {
+    if chars.peek() == Some(&'(') {
+        chars.next();
+        return true;
+    }
+
+    while chars
+        .peek()
+        .is_some_and(|next| matches!(next, '<' | '>' | '&' | '|'))
+    {
+        chars.next();
+    }
+    frame.skip_next_word = true;
+    false
+}
@@ -2,24 +2,25 @@ use rmcp::model::{CallToolResult, Content};
 use std::path::Path;
 use tokio::fs;
 
-use super::{resolve_path, ToolError};
+use super::{ToolError, Workspace};
 
 pub struct CopyPathTool {
     pub source_path: String,
     pub destination_path: String,
+    pub workspace: Workspace,
 }
 
 impl CopyPathTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
-        let source_abs = resolve_path(&self.source_path, None);
-        let dest_abs = resolve_path(&self.destination_path, None);
+        let source_abs = self.workspace.resolve_read(&self.source_path)?;
+        let dest_abs = self.workspace.resolve_write(&self.destination_path)?;
 
         if !source_abs.exists() {
             return Err(ToolError::FileNotFound(source_abs.display().to_string()));
         }
 
         if source_abs.is_dir() {
-            copy_dir_all(&source_abs, &dest_abs).await?;
+            copy_dir_all(&self.workspace, &source_abs, &dest_abs).await?;
             let message = format!("Successfully copied directory to {}", dest_abs.display());
             Ok(CallToolResult::success(vec![Content::text(message)]))
         } else {
@@ -48,6 +49,7 @@ impl CopyPathTool {
 }
 
 fn copy_dir_all<'a>(
+    workspace: &'a Workspace,
     src: &'a Path,
     dst: &'a Path,
 ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), ToolError>> + Send + 'a>> {
@@ -58,11 +60,13 @@ fn copy_dir_all<'a>(
 
         let mut entries = fs::read_dir(src).await?;
         while let Some(entry) = entries.next_entry().await? {
-            let src_path = entry.path();
-            let dest_path = dst.join(entry.file_name());
+            // Entries may be symlinks pointing outside the workspace, on
+            // either side of the copy.
+            let src_path = workspace.resolve_read(entry.path())?;
+            let dest_path = workspace.resolve_write(dst.join(entry.file_name()))?;
 
             if src_path.is_dir() {
-                copy_dir_all(&src_path, &dest_path).await?;
+                copy_dir_all(workspace, &src_path, &dest_path).await?;
             } else {
                 fs::copy(&src_path, &dest_path).await?;
             }
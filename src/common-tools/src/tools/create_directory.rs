@@ -1,15 +1,16 @@
 use rmcp::model::{CallToolResult, Content};
 use tokio::fs;
 
-use super::{resolve_path, ToolError};
+use super::{ToolError, Workspace};
 
 pub struct CreateDirectoryTool {
     pub path: String,
+    pub workspace: Workspace,
 }
 
 impl CreateDirectoryTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
-        let abs_path = resolve_path(&self.path, None);
+        let abs_path = self.workspace.resolve_write(&self.path)?;
 
         if abs_path.exists() && abs_path.is_file() {
             return Err(ToolError::InvalidArgument(format!(
@@ -0,0 +1,96 @@
+use similar::{ChangeTag, TextDiff};
+use std::path::{Component, Path};
+
+use super::process::truncate_head_tail;
+use super::Workspace;
+
+/// Longest diff included in an edit tool's success message. Dry runs return
+/// the whole diff.
+pub(crate) const MAX_DIFF_PREVIEW_BYTES: usize = 8 * 1024;
+
+/// What an edit did, or would do, to one file.
+pub(crate) struct FileDiff {
+    /// Unified diff with three lines of context, empty if nothing changed.
+    pub(crate) unified: String,
+    pub(crate) added: usize,
+    pub(crate) removed: usize,
+}
+
+impl FileDiff {
+    /// Diffs `old` against `new`, naming the file `name` in the headers.
+    pub(crate) fn new(name: &str, old: &str, new: &str) -> Self {
+        let diff = TextDiff::from_lines(old, new);
+
+        let (mut added, mut removed) = (0, 0);
+        for change in diff.iter_all_changes() {
+            match change.tag() {
+                ChangeTag::Insert => added += 1,
+                ChangeTag::Delete => removed += 1,
+                ChangeTag::Equal => {}
+            }
+        }
+
+        let unified = if added + removed == 0 {
+            String::new()
+        } else {
+            diff.unified_diff()
+                .context_radius(3)
+                .header(&format!("a/{}", name), &format!("b/{}", name))
+                .to_string()
+        };
+
+        Self {
+            unified,
+            added,
+            removed,
+        }
+    }
+
+    /// Line counts in the style of `git diff --stat`.
+    pub(crate) fn stat(&self) -> String {
+        format!("+{} -{}", self.added, self.removed)
+    }
+}
+
+/// How `path` is named in diff headers: relative to the workspace root, or
+/// to the current directory when the workspace is unrestricted. Paths
+/// outside it lose their leading `/`, as with `git diff --no-index`.
+pub(crate) fn diff_name(path: &Path, workspace: &Workspace) -> String {
+    let base = match workspace.root() {
+        Some(root) => Some(root.to_path_buf()),
+        None => std::env::current_dir()
+            .ok()
+            .map(|dir| dir.canonicalize().unwrap_or(dir)),
+    };
+    let relative = base
+        .and_then(|base| path.strip_prefix(base).ok())
+        .filter(|relative| !relative.as_os_str().is_empty())
+        .unwrap_or(path);
+
+    relative
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_) | Component::ParentDir))
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Joins the diffs of several files, keeping the start and end of the result
+/// when it is longer than `max_bytes`.
+pub(crate) fn render_diffs<'a>(
+    diffs: impl IntoIterator<Item = &'a FileDiff>,
+    max_bytes: Option<usize>,
+) -> String {
+    let text: String = diffs.into_iter().map(|d| d.unified.as_str()).collect();
+    if text.is_empty() {
+        return "No changes".to_string();
+    }
+
+    match max_bytes.and_then(|max| truncate_head_tail(&text, max)) {
+        Some(truncated) => format!(
+            "{}\n[diff truncated; use read_file to see the result]",
+            truncated.trim_end()
+        ),
+        None => text,
+    }
+}
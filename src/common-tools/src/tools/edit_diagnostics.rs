@@ -0,0 +1,142 @@
+use similar::TextDiff;
+use std::time::{Duration, Instant};
+
+/// How many near misses to describe when a search text isn't found.
+const MAX_CANDIDATES: usize = 3;
+
+/// Regions less similar than this aren't worth showing.
+const MIN_SIMILARITY: f32 = 0.5;
+
+/// Upper bound on the time spent looking for near misses in a large file.
+const SEARCH_BUDGET: Duration = Duration::from_millis(500);
+
+/// A region of the file that resembles the search text.
+struct Candidate {
+    /// Index of the first line of the region.
+    start: usize,
+    similarity: f32,
+}
+
+/// Compares two lines the way the fuzzy matcher does, ignoring differences
+/// in whitespace, and returns how alike they are from 0 to 1.
+fn line_similarity(a: &str, b: &str) -> f32 {
+    let a = normalize_whitespace(a);
+    let b = normalize_whitespace(b);
+    if a == b {
+        return 1.0;
+    }
+    TextDiff::from_chars(a.as_str(), b.as_str()).ratio()
+}
+
+pub(crate) fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Finds the regions of `content_lines` most like `search_lines`, best
+/// first, scoring each line against the line of the search text it would
+/// have to match.
+fn closest_candidates(content_lines: &[&str], search_lines: &[&str]) -> Vec<Candidate> {
+    if search_lines.is_empty() || content_lines.len() < search_lines.len() {
+        return Vec::new();
+    }
+
+    let started = Instant::now();
+    let mut scored = Vec::new();
+    for start in 0..=content_lines.len() - search_lines.len() {
+        if started.elapsed() > SEARCH_BUDGET {
+            break;
+        }
+        let total: f32 = search_lines
+            .iter()
+            .zip(&content_lines[start..])
+            .map(|(search, content)| line_similarity(search, content))
+            .sum();
+        scored.push(Candidate {
+            start,
+            similarity: total / search_lines.len() as f32,
+        });
+    }
+
+    scored.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+
+    let mut best: Vec<Candidate> = Vec::new();
+    for candidate in scored {
+        if best.len() == MAX_CANDIDATES || candidate.similarity < MIN_SIMILARITY {
+            break;
+        }
+        let overlaps = best
+            .iter()
+            .any(|b| candidate.start.abs_diff(b.start) < search_lines.len());
+        if !overlaps {
+            best.push(candidate);
+        }
+    }
+    best
+}
+
+/// Explains why `search_lines` matched nowhere, showing where the closest
+/// regions of the file diverge from it line by line.
+pub(crate) fn not_found_message(content_lines: &[&str], search_lines: &[&str]) -> String {
+    let candidates = closest_candidates(content_lines, search_lines);
+    if candidates.is_empty() {
+        return "Search text not found in file, and no region of the file resembles it. \
+                Please verify the text exists and check for typos."
+            .to_string();
+    }
+
+    let mut message = String::from(
+        "Search text not found in file. Closest candidates, with lines from the search text \
+         marked - and lines from the file marked +:",
+    );
+    for candidate in candidates {
+        let end = candidate.start + search_lines.len();
+        message.push_str(&format!(
+            "\n\n{} ({:.0}% similar):",
+            describe_lines(candidate.start, end),
+            candidate.similarity * 100.0
+        ));
+
+        for (offset, (search, content)) in search_lines
+            .iter()
+            .zip(&content_lines[candidate.start..end])
+            .enumerate()
+        {
+            let line_number = candidate.start + offset + 1;
+            if normalize_whitespace(search) == normalize_whitespace(content) {
+                message.push_str(&format!("\n{:>6}   {}", line_number, content));
+            } else {
+                message.push_str(&format!("\n{:>6} - {}", line_number, search));
+                message.push_str(&format!("\n{:>6} + {}", "", content));
+            }
+        }
+    }
+    message
+}
+
+/// Lists every place an ambiguous search text matched.
+pub(crate) fn ambiguous_message(matches: &[(usize, usize)]) -> String {
+    format!(
+        "Ambiguous: found {} potential matches, at {}. Include more surrounding lines in the \
+         search text, or pick one with occurrence or anchor_line.",
+        matches.len(),
+        match_locations(matches)
+    )
+}
+
+/// Names the lines of each match, given as 0-based, end-exclusive ranges.
+pub(crate) fn match_locations(matches: &[(usize, usize)]) -> String {
+    let locations: Vec<String> = matches
+        .iter()
+        .map(|&(start, end)| describe_lines(start, end).to_lowercase())
+        .collect();
+    locations.join(", ")
+}
+
+/// Names the lines from index `start` up to `end` for people, who count from 1.
+fn describe_lines(start: usize, end: usize) -> String {
+    if end - start <= 1 {
+        format!("Line {}", start + 1)
+    } else {
+        format!("Lines {}-{}", start + 1, end)
+    }
+}
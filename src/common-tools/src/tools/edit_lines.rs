@@ -0,0 +1,266 @@
+use rmcp::model::{CallToolResult, Content};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::atomic_write::write_files;
+use super::diff::{diff_name, render_diffs, FileDiff, MAX_DIFF_PREVIEW_BYTES};
+use super::text_document::TextDocument;
+use super::{ToolError, Workspace};
+
+/// Shortest prefix of a content hash accepted as `expected_hash`.
+const MIN_HASH_PREFIX: usize = 8;
+
+/// A change addressed by line numbers. Every line number refers to the file
+/// as it was before any of the operations, as shown by read_file.
+#[derive(Debug, Clone, Deserialize, Serialize, schemars::JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum LineOperation {
+    /// Insert content after a line; line 0 inserts at the top of the file.
+    InsertAfter { line: usize, content: String },
+    /// Replace lines start through end, inclusive, with content.
+    Replace {
+        start: usize,
+        end: usize,
+        content: String,
+        /// SHA-256 of the lines being replaced exactly as the file stores them, as printed
+        /// by `sed -n 'START,ENDp' FILE | sha256sum`: with the file's own line endings, and
+        /// no newline after a last line that lacks one. At least 8 leading hex digits are
+        /// enough.
+        /// The operation fails if the lines no longer match.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expected_hash: Option<String>,
+    },
+    /// Delete lines start through end, inclusive.
+    Delete {
+        start: usize,
+        end: usize,
+        /// SHA-256 of the lines being deleted, as for replace.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expected_hash: Option<String>,
+    },
+}
+
+pub struct EditLinesTool {
+    pub path: String,
+    pub operations: Vec<LineOperation>,
+    /// Report the diff the operations would produce without writing anything.
+    pub dry_run: bool,
+    pub workspace: Workspace,
+}
+
+/// An operation resolved against the original lines: replace the 0-based,
+/// end-exclusive range `start..end` (empty for an insertion) with `lines`.
+struct Splice {
+    start: usize,
+    end: usize,
+    lines: Vec<String>,
+    /// 1-based position of the operation in the request, for error messages.
+    index: usize,
+}
+
+impl EditLinesTool {
+    pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
+        let abs_path = self.workspace.resolve_write(&self.path)?;
+
+        if !abs_path.exists() {
+            return Err(ToolError::FileNotFound(abs_path.display().to_string()));
+        }
+        if self.operations.is_empty() {
+            return Err(ToolError::InvalidArgument(
+                "No operations were given".to_string(),
+            ));
+        }
+
+        let document = TextDocument::read(&abs_path).await?;
+        let lines = document.lines();
+
+        let splices = self.resolve(&document, &lines)?;
+
+        let mut new_lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+        for splice in splices.into_iter().rev() {
+            new_lines.splice(splice.start..splice.end, splice.lines);
+        }
+
+        let new_content = document.join_lines(&new_lines);
+
+        let diff = FileDiff::new(
+            &diff_name(&abs_path, &self.workspace),
+            &document.text,
+            &new_content,
+        );
+        if self.dry_run {
+            let message = format!(
+                "Dry run, nothing was written. Applying {} operations to {} would change it ({})\n\n{}",
+                self.operations.len(),
+                abs_path.display(),
+                diff.stat(),
+                render_diffs([&diff], None)
+            );
+            return Ok(CallToolResult::success(vec![Content::text(message)]));
+        }
+
+        let writes = vec![(abs_path.clone(), document.render(&new_content))];
+        tokio::task::spawn_blocking(move || write_files(&writes))
+            .await
+            .map_err(|e| ToolError::Other(e.to_string()))??;
+
+        let message = format!(
+            "Successfully applied {} operations to {} ({})\n\n{}",
+            self.operations.len(),
+            abs_path.display(),
+            diff.stat(),
+            render_diffs([&diff], Some(MAX_DIFF_PREVIEW_BYTES))
+        );
+        Ok(CallToolResult::success(vec![Content::text(message)]))
+    }
+
+    /// Checks every operation against the original lines and returns them in
+    /// file order, rejecting ranges that overlap.
+    fn resolve(&self, document: &TextDocument, lines: &[&str]) -> Result<Vec<Splice>, ToolError> {
+        let mut splices = Vec::with_capacity(self.operations.len());
+
+        for (i, operation) in self.operations.iter().enumerate() {
+            let index = i + 1;
+            let invalid = |message: String| {
+                ToolError::InvalidArgument(format!("Operation {}: {}", index, message))
+            };
+
+            let splice = match operation {
+                LineOperation::InsertAfter { line, content } => {
+                    if *line > lines.len() {
+                        return Err(invalid(format!(
+                            "line {} is past the end of the file, which has {} lines",
+                            line,
+                            lines.len()
+                        )));
+                    }
+                    Splice {
+                        start: *line,
+                        end: *line,
+                        lines: content_lines(content),
+                        index,
+                    }
+                }
+                LineOperation::Replace {
+                    start,
+                    end,
+                    content,
+                    expected_hash,
+                } => {
+                    let range =
+                        check_range(document, lines, *start, *end, expected_hash.as_deref())
+                            .map_err(invalid)?;
+                    Splice {
+                        start: range.0,
+                        end: range.1,
+                        lines: content_lines(content),
+                        index,
+                    }
+                }
+                LineOperation::Delete {
+                    start,
+                    end,
+                    expected_hash,
+                } => {
+                    let range =
+                        check_range(document, lines, *start, *end, expected_hash.as_deref())
+                            .map_err(invalid)?;
+                    Splice {
+                        start: range.0,
+                        end: range.1,
+                        lines: Vec::new(),
+                        index,
+                    }
+                }
+            };
+            splices.push(splice);
+        }
+
+        // Stable, so insertions after the same line keep their order.
+        splices.sort_by_key(|s| (s.start, s.end));
+
+        // An insertion may sit right before or after a range, but not inside one.
+        let mut furthest: Option<&Splice> = None;
+        for splice in &splices {
+            if let Some(previous) = furthest {
+                if splice.start < previous.end {
+                    return Err(ToolError::InvalidArgument(format!(
+                        "Operations {} and {} overlap",
+                        previous.index.min(splice.index),
+                        previous.index.max(splice.index)
+                    )));
+                }
+            }
+            if furthest.is_none_or(|previous| splice.end > previous.end) {
+                furthest = Some(splice);
+            }
+        }
+
+        Ok(splices)
+    }
+}
+
+/// Validates a 1-based inclusive range and its expected hash, returning it
+/// as a 0-based end-exclusive range.
+fn check_range(
+    document: &TextDocument,
+    lines: &[&str],
+    start: usize,
+    end: usize,
+    expected_hash: Option<&str>,
+) -> Result<(usize, usize), String> {
+    if start == 0 || end < start {
+        return Err(format!(
+            "lines {}-{} are not a valid range; line numbers start at 1 and end must not be \
+             before start",
+            start, end
+        ));
+    }
+    if end > lines.len() {
+        return Err(format!(
+            "lines {}-{} extend past the end of the file, which has {} lines",
+            start,
+            end,
+            lines.len()
+        ));
+    }
+
+    let range = &lines[start - 1..end];
+    if let Some(expected) = expected_hash {
+        let expected = expected.trim().to_ascii_lowercase();
+        if expected.len() < MIN_HASH_PREFIX {
+            return Err(format!(
+                "expected_hash must have at least {} hex digits",
+                MIN_HASH_PREFIX
+            ));
+        }
+        let actual = content_hash(&document.raw_lines(start - 1, end));
+        if !actual.starts_with(&expected) {
+            return Err(format!(
+                "lines {}-{} have changed: expected hash {} but found {}. They now read:\n{}",
+                start,
+                end,
+                expected,
+                actual,
+                range.join("\n")
+            ));
+        }
+    }
+
+    Ok((start - 1, end))
+}
+
+/// SHA-256 of `text`, in hex.
+fn content_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/// Splits new content into lines; a single trailing newline doesn't start
+/// another line, and empty content is no lines at all.
+fn content_lines(content: &str) -> Vec<String> {
+    let content = content.strip_suffix('\n').unwrap_or(content);
+    if content.is_empty() {
+        return Vec::new();
+    }
+    content.split('\n').map(str::to_string).collect()
+}
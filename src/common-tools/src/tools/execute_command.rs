@@ -1,56 +1,686 @@
-use rmcp::model::{CallToolResult, Content};
-use std::process::Stdio;
-use tokio::process::Command;
+use rmcp::model::{CallToolResult, Content, ProgressNotificationParam, ProgressToken};
+use rmcp::service::{Peer, RoleServer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStderr, Command};
+use tokio_util::sync::CancellationToken;
 
-use super::ToolError;
+use super::command_policy::{enforce_decision, parse_argv};
+use super::process::{
+    combine_output, kill_process_group, truncate_head_tail, CapturedOutput, ProcessRegistry,
+};
+use super::pty::{render_terminal_output, Pty, PtySize};
+use super::resource_limits::ResourceLimits;
+use super::{CommandPolicy, PolicyDecision, ToolError, Workspace};
+
+pub const DEFAULT_COMMAND_TIMEOUT_MS: u64 = 600_000;
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// How often a running command reports new output as progress.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest output excerpt sent in a single progress notification.
+const MAX_PROGRESS_MESSAGE_BYTES: usize = 2048;
+
+/// A shell that can interpret `command` in place of the platform default
+/// (`sh` on Unix, `cmd` on Windows).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Sh,
+    Bash,
+    Zsh,
+    Pwsh,
+    Cmd,
+}
+
+impl Shell {
+    fn platform_default() -> Self {
+        if cfg!(target_os = "windows") {
+            Shell::Cmd
+        } else {
+            Shell::Sh
+        }
+    }
+
+    fn command(self, script: &str) -> Command {
+        let (program, flags): (&str, &[&str]) = match self {
+            Shell::Sh => ("sh", &["-c"]),
+            Shell::Bash => ("bash", &["-c"]),
+            Shell::Zsh => ("zsh", &["-c"]),
+            Shell::Pwsh => ("pwsh", &["-NoProfile", "-NonInteractive", "-Command"]),
+            Shell::Cmd => ("cmd", &["/C"]),
+        };
+
+        let mut cmd = Command::new(program);
+        cmd.args(flags).arg(script);
+        cmd
+    }
+}
+
+/// What `execute_command` runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invocation {
+    /// A command line interpreted by a shell.
+    Shell {
+        command: String,
+        /// `None` selects the platform default shell.
+        shell: Option<Shell>,
+    },
+    /// A program spawned directly, so its arguments are never reinterpreted.
+    Direct { program: String, args: Vec<String> },
+}
+
+impl Invocation {
+    /// A command line for the platform default shell.
+    pub fn shell(command: impl Into<String>) -> Self {
+        Invocation::Shell {
+            command: command.into(),
+            shell: None,
+        }
+    }
+
+    /// Builds an invocation from the `command` or `program` form of the tool's
+    /// parameters, which are mutually exclusive.
+    pub fn new(
+        command: Option<String>,
+        program: Option<String>,
+        args: Option<Vec<String>>,
+        shell: Option<Shell>,
+    ) -> Result<Self, ToolError> {
+        match (command, program) {
+            (Some(command), None) => {
+                if args.is_some() {
+                    return Err(ToolError::InvalidArgument(
+                        "args can only be used with program, not command".to_string(),
+                    ));
+                }
+                Ok(Invocation::Shell { command, shell })
+            }
+            (None, Some(program)) => {
+                if shell.is_some() {
+                    return Err(ToolError::InvalidArgument(
+                        "shell can only be used with command, not program".to_string(),
+                    ));
+                }
+                Ok(Invocation::Direct {
+                    program,
+                    args: args.unwrap_or_default(),
+                })
+            }
+            (Some(_), Some(_)) => Err(ToolError::InvalidArgument(
+                "Pass either command or program, not both".to_string(),
+            )),
+            (None, None) => Err(ToolError::InvalidArgument(
+                "Either command or program is required".to_string(),
+            )),
+        }
+    }
+
+    fn build(&self) -> Command {
+        match self {
+            Invocation::Shell { command, shell } => shell
+                .unwrap_or_else(Shell::platform_default)
+                .command(command),
+            Invocation::Direct { program, args } => {
+                let mut cmd = Command::new(program);
+                cmd.args(args);
+                cmd
+            }
+        }
+    }
+
+    fn evaluate(&self, policy: &CommandPolicy, working_directory: &Path) -> PolicyDecision {
+        match self {
+            Invocation::Shell { command, .. } => policy.evaluate(command, working_directory),
+            Invocation::Direct { program, args } => policy.evaluate_commands(
+                &parse_argv(program, args),
+                &self.to_string(),
+                working_directory,
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Invocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Invocation::Shell { command, .. } => write!(f, "{}", command),
+            Invocation::Direct { program, args } => {
+                write!(f, "{}", quote_word(program))?;
+                for arg in args {
+                    write!(f, " {}", quote_word(arg))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Quotes a word for display the way a POSIX shell would need it.
+fn quote_word(word: &str) -> String {
+    let is_plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if is_plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
 
 pub struct ExecuteCommandTool {
-    pub command: String,
+    pub invocation: Invocation,
+    pub working_directory: Option<String>,
+    pub env: HashMap<String, String>,
+    pub env_clear: bool,
+    pub stdin: Option<String>,
+    /// Run under a pseudo-terminal of this size instead of with pipes.
+    pub pty: Option<PtySize>,
+    pub timeout_ms: Option<u64>,
+    pub limits: ResourceLimits,
+    pub max_output_bytes: usize,
+    /// Where the full text of truncated output is saved; without it, output
+    /// is only truncated.
+    pub output_dir: Option<PathBuf>,
+    pub background: bool,
+    pub cancellation_token: CancellationToken,
+    pub workspace: Workspace,
+    pub processes: ProcessRegistry,
+    pub policy: CommandPolicy,
+    /// Used to ask the user about commands the policy wants confirmed, and to
+    /// send progress notifications.
+    pub peer: Option<Peer<RoleServer>>,
+    /// Token from the request's `_meta`; when set, output is streamed to the
+    /// client as progress notifications while the command runs.
+    pub progress_token: Option<ProgressToken>,
+}
+
+/// The structured content of every foreground `execute_command` result.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct CommandOutcome {
+    /// Exit code, absent when the process was killed by a signal.
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the process (Unix only).
+    pub signal: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+    pub timed_out: bool,
+    pub cancelled: bool,
+    #[serde(flatten)]
+    pub files: OutputFiles,
+}
+
+/// Where the full text of truncated output was saved.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct OutputFiles {
+    /// Full stdout, when `stdout` had to be truncated.
+    pub stdout_file: Option<String>,
+    /// Full stderr, when `stderr` had to be truncated.
+    pub stderr_file: Option<String>,
+}
+
+impl OutputFiles {
+    /// Tells the agent where to find each truncated stream's full text.
+    pub(crate) fn append_notes(&self, output: &mut String) {
+        for (stream, file) in [("stdout", &self.stdout_file), ("stderr", &self.stderr_file)] {
+            if let Some(file) = file {
+                output.push_str(&format!(
+                    "\n[{} was truncated; the full output is in {}]",
+                    stream, file
+                ));
+            }
+        }
+    }
+}
+
+impl CommandOutcome {
+    fn succeeded(&self) -> bool {
+        self.exit_code == Some(0) && !self.timed_out && !self.cancelled
+    }
+
+    fn summary(&self, timeout_ms: Option<u64>) -> String {
+        let mut output = combine_output(&self.stdout, &self.stderr);
+        self.files.append_notes(&mut output);
+
+        if self.succeeded() {
+            output
+        } else if self.timed_out {
+            format!(
+                "Command timed out after {} ms; partial output:\n{}",
+                timeout_ms.unwrap_or_default(),
+                output
+            )
+        } else if self.cancelled {
+            format!("Command was cancelled; partial output:\n{}", output)
+        } else if let Some(code) = self.exit_code {
+            format!("Command failed with exit code {}:\n{}", code, output)
+        } else {
+            let signal = self.signal.unwrap_or(-1);
+            match signal_explanation(signal) {
+                Some(explanation) => format!(
+                    "Command terminated by signal {} ({}):\n{}",
+                    signal, explanation, output
+                ),
+                None => format!("Command terminated by signal {}:\n{}", signal, output),
+            }
+        }
+    }
+}
+
+/// Truncates a stream to `max_bytes`, spilling the complete text to a file
+/// in `dir` the agent can page through with read_file.
+pub(crate) fn cap_output(
+    stream: &str,
+    output: String,
+    max_bytes: usize,
+    dir: Option<&Path>,
+) -> Result<(String, Option<String>), ToolError> {
+    let Some(truncated) = truncate_head_tail(&output, max_bytes) else {
+        return Ok((output, None));
+    };
+    let Some(mut file) = spill_file(stream, dir)? else {
+        return Ok((truncated, None));
+    };
+
+    file.write_all(output.as_bytes())?;
+    let (_, path) = file.keep().map_err(|e| ToolError::Io(e.error))?;
+
+    Ok((truncated, Some(path.display().to_string())))
+}
+
+/// A new file in `dir` for the full text of a stream, if there is a `dir`.
+fn spill_file(stream: &str, dir: Option<&Path>) -> Result<Option<NamedTempFile>, ToolError> {
+    let Some(dir) = dir else {
+        return Ok(None);
+    };
+    let file = tempfile::Builder::new()
+        .prefix("command-")
+        .suffix(&format!(".{}.log", stream))
+        .tempfile_in(dir)?;
+    Ok(Some(file))
+}
+
+/// Describes the signals a command gets for exceeding a resource limit.
+fn signal_explanation(signal: i32) -> Option<&'static str> {
+    #[cfg(unix)]
+    {
+        match signal {
+            libc::SIGXCPU => Some("CPU time limit exceeded"),
+            libc::SIGXFSZ => Some("file size limit exceeded"),
+            libc::SIGKILL => Some("killed, possibly for exceeding the CPU time limit"),
+            _ => None,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = signal;
+        None
+    }
+}
+
+enum Completion {
+    Exited(ExitStatus),
+    TimedOut,
+    Cancelled,
 }
 
 impl ExecuteCommandTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
-        let mut cmd = if cfg!(target_os = "windows") {
-            let mut c = Command::new("cmd");
-            c.args(["/C", &self.command]);
-            c
+        if self.background && self.pty.is_some() {
+            return Err(ToolError::InvalidArgument(
+                "pty cannot be combined with background".to_string(),
+            ));
+        }
+
+        let working_directory = self.resolve_working_directory()?;
+        self.check_policy(working_directory.as_deref()).await?;
+
+        let (mut cmd, pty) = self.build_command(working_directory)?;
+
+        let started = Instant::now();
+        let mut child = cmd.spawn().map_err(|e| {
+            ToolError::CommandFailed(format!("Failed to execute {}: {}", self.invocation, e))
+        })?;
+        // Close our copies of the terminal so reading it ends with the command.
+        drop(cmd);
+
+        if self.background {
+            return self.start_background(child).await;
+        }
+
+        // Input is written from a separate task so a command that produces
+        // output before reading all of its input can't deadlock us.
+        if let Some(input) = self.stdin.clone() {
+            if let Some(pty) = pty.clone() {
+                tokio::spawn(async move {
+                    let _ = pty.write_all(input.as_bytes()).await;
+                });
+            } else if let Some(mut pipe) = child.stdin.take() {
+                tokio::spawn(async move {
+                    let _ = pipe.write_all(input.as_bytes()).await;
+                });
+            }
+        }
+
+        // Output is written to spill files as it arrives, so only the start
+        // and end of it are ever held in memory.
+        let output_dir = self.output_dir.as_deref();
+        let (stdout_spill, stderr_spill) = (
+            spill_file("stdout", output_dir)?,
+            spill_file("stderr", output_dir)?,
+        );
+        let (stdout, stderr) = match &pty {
+            Some(pty) => (
+                CapturedOutput::capture_spilled(
+                    Some(pty.reader()),
+                    self.max_output_bytes,
+                    stdout_spill,
+                ),
+                CapturedOutput::capture_spilled(
+                    None::<ChildStderr>,
+                    self.max_output_bytes,
+                    stderr_spill,
+                ),
+            ),
+            None => (
+                CapturedOutput::capture_spilled(
+                    child.stdout.take(),
+                    self.max_output_bytes,
+                    stdout_spill,
+                ),
+                CapturedOutput::capture_spilled(
+                    child.stderr.take(),
+                    self.max_output_bytes,
+                    stderr_spill,
+                ),
+            ),
+        };
+
+        let completion = self
+            .wait_for_completion(&mut child, &stdout, &stderr)
+            .await?;
+        let status = match completion {
+            Completion::Exited(status) => Some(status),
+            Completion::TimedOut | Completion::Cancelled => kill_process_group(&mut child).await,
+        };
+
+        let render = |output| self.render(output);
+        let (stdout, stdout_file) = stdout.finish_capped(render).await;
+        let (stderr, stderr_file) = stderr.finish_capped(render).await;
+
+        let outcome = CommandOutcome {
+            exit_code: status.and_then(|s| s.code()),
+            signal: status.and_then(exit_signal),
+            stdout,
+            stderr,
+            duration_ms: started.elapsed().as_millis() as u64,
+            timed_out: matches!(completion, Completion::TimedOut),
+            cancelled: matches!(completion, Completion::Cancelled),
+            files: OutputFiles {
+                stdout_file,
+                stderr_file,
+            },
+        };
+
+        let content = vec![Content::text(outcome.summary(self.effective_timeout_ms()))];
+        let mut result = if outcome.succeeded() {
+            CallToolResult::success(content)
+        } else {
+            CallToolResult::error(content)
+        };
+        result.structured_content =
+            Some(serde_json::to_value(&outcome).map_err(|e| ToolError::Other(e.to_string()))?);
+
+        Ok(result)
+    }
+
+    /// The call's timeout, or its wall time limit if that is shorter.
+    fn effective_timeout_ms(&self) -> Option<u64> {
+        self.limits.wall_timeout_ms(self.timeout_ms)
+    }
+
+    /// Turns what a pseudo-terminal displayed into plain text.
+    fn render(&self, output: String) -> String {
+        if self.pty.is_some() {
+            render_terminal_output(&output)
         } else {
-            let mut c = Command::new("sh");
-            c.args(["-c", &self.command]);
-            c
+            output
+        }
+    }
+
+    async fn start_background(&self, mut child: Child) -> Result<CallToolResult, ToolError> {
+        if let (Some(input), Some(pipe)) = (&self.stdin, child.stdin.as_mut()) {
+            pipe.write_all(input.as_bytes()).await?;
+        }
+
+        let pid = child.id();
+        let id = self.processes.register(
+            &self.invocation.to_string(),
+            child,
+            self.limits.wall_time_ms.map(Duration::from_millis),
+            self.max_output_bytes,
+        );
+
+        let message = format!(
+            "Started background process {} (pid {}). Use process_output to read its output \
+             and process_kill to stop it.",
+            id,
+            pid.map_or_else(|| "unknown".to_string(), |p| p.to_string())
+        );
+        Ok(CallToolResult::success(vec![Content::text(message)]))
+    }
+
+    async fn check_policy(&self, working_directory: Option<&Path>) -> Result<(), ToolError> {
+        let current_dir;
+        let dir = match working_directory {
+            Some(dir) => dir,
+            None => {
+                current_dir = std::env::current_dir()?;
+                &current_dir
+            }
+        };
+
+        let decision = self.invocation.evaluate(&self.policy, dir);
+        enforce_decision(
+            &decision,
+            &self.invocation.to_string(),
+            dir,
+            self.peer.as_ref(),
+            &self.cancellation_token,
+        )
+        .await
+    }
+
+    fn resolve_working_directory(&self) -> Result<Option<PathBuf>, ToolError> {
+        let dir = match &self.working_directory {
+            Some(dir) => self.workspace.resolve_write(dir)?,
+            None => match self.workspace.root() {
+                Some(root) => root.to_path_buf(),
+                None => return Ok(None),
+            },
+        };
+
+        if !dir.is_dir() {
+            return Err(ToolError::InvalidArgument(format!(
+                "Working directory is not a directory: {}",
+                dir.display()
+            )));
+        }
+
+        Ok(Some(dir))
+    }
+
+    fn build_command(
+        &self,
+        working_directory: Option<PathBuf>,
+    ) -> Result<(Command, Option<Pty>), ToolError> {
+        let mut cmd = self.invocation.build();
+
+        if let Some(dir) = working_directory {
+            cmd.current_dir(dir);
+        }
+
+        if self.env_clear {
+            cmd.env_clear();
+        }
+        cmd.envs(&self.env);
+        cmd.kill_on_drop(true);
+        self.limits.apply(&mut cmd)?;
+
+        if let Some(size) = self.pty {
+            if !self.env.contains_key("TERM") {
+                cmd.env("TERM", "xterm-256color");
+            }
+            // The terminal puts the command in a session and process group of
+            // its own.
+            let pty = Pty::attach(&mut cmd, size).map_err(|e| {
+                ToolError::CommandFailed(format!("Failed to open a pseudo-terminal: {}", e))
+            })?;
+            return Ok((cmd, Some(pty)));
+        }
+
+        // Background processes keep stdin open for process_write_stdin.
+        let stdin = if self.background || self.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
         };
 
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .stdin(Stdio::null());
+            .stdin(stdin);
 
-        let output = cmd
-            .output()
-            .await
-            .map_err(|e| ToolError::CommandFailed(format!("Failed to execute command: {}", e)))?;
+        // Run the command in its own process group so a timeout can take down
+        // everything it spawned, not just the shell.
+        #[cfg(unix)]
+        cmd.process_group(0);
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
+        Ok((cmd, None))
+    }
 
-        let mut result = String::new();
-        if !stdout.is_empty() {
-            result.push_str(&stdout);
-        }
-        if !stderr.is_empty() {
-            if !result.is_empty() {
-                result.push_str("\n--- STDERR ---\n");
+    async fn wait_for_completion(
+        &self,
+        child: &mut Child,
+        stdout: &CapturedOutput,
+        stderr: &CapturedOutput,
+    ) -> Result<Completion, ToolError> {
+        let timeout = async {
+            match self.effective_timeout_ms() {
+                Some(ms) => tokio::time::sleep(Duration::from_millis(ms)).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(timeout);
+
+        let mut progress = match (&self.peer, &self.progress_token) {
+            (Some(peer), Some(token)) => Some(ProgressReporter {
+                peer,
+                token,
+                render: self.pty.is_some(),
+                stdout_seen: 0,
+                stderr_seen: 0,
+            }),
+            _ => None,
+        };
+        let mut ticker = tokio::time::interval_at(
+            tokio::time::Instant::now() + PROGRESS_INTERVAL,
+            PROGRESS_INTERVAL,
+        );
+
+        loop {
+            tokio::select! {
+                status = child.wait() => return Ok(Completion::Exited(status?)),
+                _ = &mut timeout => return Ok(Completion::TimedOut),
+                _ = self.cancellation_token.cancelled() => return Ok(Completion::Cancelled),
+                _ = ticker.tick(), if progress.is_some() => {
+                    if let Some(progress) = progress.as_mut() {
+                        progress.report(stdout, stderr).await;
+                    }
+                }
             }
-            result.push_str(&stderr);
         }
+    }
+}
 
-        if !output.status.success() {
-            return Err(ToolError::CommandFailed(format!(
-                "Command failed with exit code {}: {}",
-                output.status.code().unwrap_or(-1),
-                result
-            )));
+/// Streams newly captured output to the client as progress notifications.
+/// `progress` is the number of output bytes seen so far, so it only grows.
+struct ProgressReporter<'a> {
+    peer: &'a Peer<RoleServer>,
+    token: &'a ProgressToken,
+    /// Whether the output comes from a pseudo-terminal.
+    render: bool,
+    stdout_seen: usize,
+    stderr_seen: usize,
+}
+
+impl ProgressReporter<'_> {
+    async fn report(&mut self, stdout: &CapturedOutput, stderr: &CapturedOutput) {
+        let (new_stdout, stdout_seen) = stdout.read_since(self.stdout_seen);
+        let (new_stderr, stderr_seen) = stderr.read_since(self.stderr_seen);
+        if stdout_seen == self.stdout_seen && stderr_seen == self.stderr_seen {
+            return;
         }
+        self.stdout_seen = stdout_seen;
+        self.stderr_seen = stderr_seen;
+
+        let mut message = combine_output(&new_stdout, &new_stderr);
+        if self.render {
+            message = render_terminal_output(&message);
+        }
+        let message = match truncate_head(&message, MAX_PROGRESS_MESSAGE_BYTES) {
+            Some(tail) => format!("…{}", tail),
+            None => message,
+        };
+
+        // Progress is best effort; a client that went away will see the
+        // command cancelled instead.
+        let _ = self
+            .peer
+            .notify_progress(ProgressNotificationParam {
+                progress_token: self.token.clone(),
+                progress: (stdout_seen + stderr_seen) as f64,
+                total: None,
+                message: Some(message),
+            })
+            .await;
+    }
+}
+
+/// Returns the last `max_bytes` of `text`, starting at a line boundary where
+/// possible, or `None` if `text` already fits.
+fn truncate_head(text: &str, max_bytes: usize) -> Option<&str> {
+    if text.len() <= max_bytes {
+        return None;
+    }
 
-        Ok(CallToolResult::success(vec![Content::text(result)]))
+    let mut start = text.len() - max_bytes;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    if let Some(newline) = text[start..].find('\n') {
+        if start + newline + 1 < text.len() {
+            start += newline + 1;
+        }
     }
+    Some(&text[start..])
+}
+
+#[cfg(unix)]
+fn exit_signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: ExitStatus) -> Option<i32> {
+    None
 }
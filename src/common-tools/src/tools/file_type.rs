@@ -0,0 +1,119 @@
+use std::path::Path;
+
+const SAMPLE_SIZE_FOR_TEXT_DETECTION: usize = 8192;
+const NON_TEXT_RATIO_THRESHOLD: f64 = 0.3;
+
+pub(crate) struct FileType;
+
+impl FileType {
+    const MAGIC_NUMBERS: &'static [(&'static [u8], &'static str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x7fELF", "application/x-elf"),
+        (b"\0asm", "application/wasm"),
+    ];
+
+    const TEXT_EXTENSIONS: &'static [&'static str] = &[
+        "txt", "rs", "py", "js", "ts", "jsx", "tsx", "html", "css", "scss", "json", "yaml", "yml",
+        "toml", "md", "xml", "svg", "sh", "bash", "c", "cpp", "h", "hpp", "java", "kt", "go",
+        "php", "rb", "lua", "sql", "conf", "log", "ini",
+    ];
+
+    const SPECIAL_FILES: &'static [&'static str] = &[
+        "Dockerfile",
+        "Makefile",
+        "Rakefile",
+        "Gemfile",
+        "README",
+        "LICENSE",
+        ".gitignore",
+        ".env",
+    ];
+
+    pub(crate) fn is_likely_text_file(path: &Path) -> bool {
+        Self::has_text_extension(path) || Self::is_special_file(path)
+    }
+
+    fn has_text_extension(path: &Path) -> bool {
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            Self::TEXT_EXTENSIONS.contains(&ext)
+        } else {
+            false
+        }
+    }
+
+    fn is_special_file(path: &Path) -> bool {
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            Self::SPECIAL_FILES.contains(&name)
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn is_likely_utf8_text(content: &str) -> bool {
+        if content.is_empty() {
+            return true;
+        }
+
+        let mut sample_size = std::cmp::min(content.len(), SAMPLE_SIZE_FOR_TEXT_DETECTION);
+        while !content.is_char_boundary(sample_size) {
+            sample_size -= 1;
+        }
+        let sample = &content[..sample_size];
+
+        let null_bytes = sample.bytes().filter(|&b| b == 0).count();
+        let non_printable = sample
+            .chars()
+            .filter(|&c| c.is_control() && c != '\n' && c != '\r' && c != '\t')
+            .count();
+
+        let total_chars = sample.chars().count();
+        if total_chars == 0 {
+            return false;
+        }
+
+        let non_text_ratio = (null_bytes + non_printable) as f64 / total_chars as f64;
+        non_text_ratio < NON_TEXT_RATIO_THRESHOLD
+    }
+
+    /// Guesses a MIME type from well-known magic numbers, falling back to the
+    /// file extension.
+    pub(crate) fn guess_mime(path: &Path, bytes: &[u8]) -> &'static str {
+        Self::sniff_mime(bytes)
+            .or_else(|| Self::guess_mime_from_extension(path))
+            .unwrap_or("application/octet-stream")
+    }
+
+    /// Identifies a MIME type from the file contents alone.
+    pub(crate) fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+        if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+            return Some("image/webp");
+        }
+
+        Self::MAGIC_NUMBERS
+            .iter()
+            .find(|(magic, _)| bytes.starts_with(magic))
+            .map(|(_, mime)| *mime)
+    }
+
+    fn guess_mime_from_extension(path: &Path) -> Option<&'static str> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        let mime = match ext.as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            "pdf" => "application/pdf",
+            "zip" | "jar" => "application/zip",
+            "gz" | "tgz" => "application/gzip",
+            "wasm" => "application/wasm",
+            _ => return None,
+        };
+        Some(mime)
+    }
+}
@@ -4,7 +4,7 @@ use rmcp::model::{CallToolResult, Content};
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
-use super::{resolve_path, ToolError};
+use super::{file_type::FileType, ToolError, Workspace};
 
 #[derive(Debug, Clone, Copy)]
 struct LineNumber(usize);
@@ -56,7 +56,8 @@ impl SearchConfiguration {
     fn new(tool: &GrepTool) -> Result<Self, ToolError> {
         let regex = Self::compile_regex(&tool.regex, tool.case_sensitive)?;
         let file_filter = Self::create_file_filter(&tool.include_pattern)?;
-        let search_directory = Self::resolve_search_directory(&tool.working_directory);
+        let search_directory =
+            Self::resolve_search_directory(&tool.workspace, &tool.working_directory)?;
 
         Ok(Self {
             regex,
@@ -90,10 +91,13 @@ impl SearchConfiguration {
         }
     }
 
-    fn resolve_search_directory(working_directory: &Option<String>) -> PathBuf {
+    fn resolve_search_directory(
+        workspace: &Workspace,
+        working_directory: &Option<String>,
+    ) -> Result<PathBuf, ToolError> {
         match working_directory {
-            Some(dir) => resolve_path(dir, None),
-            None => resolve_path(".", None),
+            Some(dir) => workspace.resolve_read(dir),
+            None => workspace.resolve_read("."),
         }
     }
 }
@@ -104,12 +108,11 @@ pub struct GrepTool {
     pub offset: u32,
     pub case_sensitive: bool,
     pub working_directory: Option<String>,
+    pub workspace: Workspace,
 }
 
 const MAX_RESULTS_PER_PAGE: u32 = 20;
 const MAX_LINE_LENGTH: usize = 10_000;
-const SAMPLE_SIZE_FOR_TEXT_DETECTION: usize = 8192;
-const NON_TEXT_RATIO_THRESHOLD: f64 = 0.3;
 
 impl GrepTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
@@ -347,67 +350,3 @@ impl FileMatch {
         }
     }
 }
-
-struct FileType;
-
-impl FileType {
-    const TEXT_EXTENSIONS: &'static [&'static str] = &[
-        "txt", "rs", "py", "js", "ts", "jsx", "tsx", "html", "css", "scss", "json", "yaml", "yml",
-        "toml", "md", "xml", "svg", "sh", "bash", "c", "cpp", "h", "hpp", "java", "kt", "go",
-        "php", "rb", "lua", "sql", "conf", "log", "ini",
-    ];
-
-    const SPECIAL_FILES: &'static [&'static str] = &[
-        "Dockerfile",
-        "Makefile",
-        "Rakefile",
-        "Gemfile",
-        "README",
-        "LICENSE",
-        ".gitignore",
-        ".env",
-    ];
-
-    fn is_likely_text_file(path: &Path) -> bool {
-        Self::has_text_extension(path) || Self::is_special_file(path)
-    }
-
-    fn has_text_extension(path: &Path) -> bool {
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-            Self::TEXT_EXTENSIONS.contains(&ext)
-        } else {
-            false
-        }
-    }
-
-    fn is_special_file(path: &Path) -> bool {
-        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-            Self::SPECIAL_FILES.contains(&name)
-        } else {
-            false
-        }
-    }
-
-    fn is_likely_utf8_text(content: &str) -> bool {
-        if content.is_empty() {
-            return true;
-        }
-
-        let sample_size = std::cmp::min(content.len(), SAMPLE_SIZE_FOR_TEXT_DETECTION);
-        let sample = &content[..sample_size];
-
-        let null_bytes = sample.bytes().filter(|&b| b == 0).count();
-        let non_printable = sample
-            .chars()
-            .filter(|&c| c.is_control() && c != '\n' && c != '\r' && c != '\t')
-            .count();
-
-        let total_chars = sample.chars().count();
-        if total_chars == 0 {
-            return false;
-        }
-
-        let non_text_ratio = (null_bytes + non_printable) as f64 / total_chars as f64;
-        non_text_ratio < NON_TEXT_RATIO_THRESHOLD
-    }
-}
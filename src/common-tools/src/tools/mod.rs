@@ -1,32 +1,70 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 pub mod apply_patch;
+mod atomic_write;
+pub mod command_policy;
 pub mod copy_path;
 pub mod create_directory;
+mod diff;
+mod edit_diagnostics;
+pub mod edit_lines;
 pub mod execute_command;
+mod file_type;
 pub mod grep;
 pub mod list_directory;
 pub mod move_path;
 pub mod now;
+mod patch_set;
+pub mod process;
+pub mod process_kill;
+pub mod process_list;
+pub mod process_output;
+pub mod process_write_stdin;
+pub mod pty;
 pub mod read_file;
+pub mod resource_limits;
 pub mod search_replace_edit;
+pub mod shell_session;
+pub mod shell_session_close;
+pub mod shell_session_create;
+pub mod shell_session_run;
 pub mod task_complete;
+mod text_document;
+pub mod workspace;
 pub mod write_file;
 
 #[cfg(test)]
 mod tests;
 
-pub use apply_patch::ApplyPatchTool;
+pub use apply_patch::{ApplyPatchTool, DEFAULT_FUZZ};
+pub use command_policy::{CommandPolicy, PolicyAction, PolicyDecision};
 pub use copy_path::CopyPathTool;
 pub use create_directory::CreateDirectoryTool;
-pub use execute_command::ExecuteCommandTool;
+pub use edit_lines::{EditLinesTool, LineOperation};
+pub use execute_command::{
+    CommandOutcome, ExecuteCommandTool, Invocation, OutputFiles, Shell, DEFAULT_COMMAND_TIMEOUT_MS,
+    DEFAULT_MAX_OUTPUT_BYTES,
+};
 pub use grep::GrepTool;
 pub use list_directory::ListDirectoryTool;
 pub use move_path::MovePathTool;
 pub use now::NowTool;
-pub use read_file::ReadFileTool;
-pub use search_replace_edit::{EditOperation, SearchReplaceEditTool};
+pub use process::{ProcessRegistry, ProcessStatus};
+pub use process_kill::ProcessKillTool;
+pub use process_list::ProcessListTool;
+pub use process_output::ProcessOutputTool;
+pub use process_write_stdin::ProcessWriteStdinTool;
+pub use pty::PtySize;
+pub use read_file::{ReadFileTool, DEFAULT_LINE_LIMIT, DEFAULT_MAX_BYTES};
+pub use resource_limits::ResourceLimits;
+pub use search_replace_edit::{EditMode, EditOperation, FileEdits, SearchReplaceEditTool};
+pub use shell_session::ShellSessions;
+pub use shell_session_close::ShellSessionCloseTool;
+pub use shell_session_create::ShellSessionCreateTool;
+pub use shell_session_run::{ShellCommandOutcome, ShellSessionRunTool};
 pub use task_complete::TaskCompleteTool;
+pub use workspace::Workspace;
 pub use write_file::WriteFileTool;
 
 #[derive(Debug, thiserror::Error)]
@@ -37,16 +75,20 @@ pub enum ToolError {
     FileNotFound(String),
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
+    #[error("Path is outside the workspace: {0}")]
+    OutsideWorkspace(String),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Command blocked by policy: {0}")]
+    PolicyViolation(String),
     #[error("Command execution failed: {0}")]
     CommandFailed(String),
     #[error("Tool error: {0}")]
     Other(String),
 }
 
-pub fn resolve_path(path: &str, working_directory: Option<&Path>) -> PathBuf {
-    let path_obj = Path::new(path);
+pub fn resolve_path(path: impl AsRef<Path>, working_directory: Option<&Path>) -> PathBuf {
+    let path_obj = path.as_ref();
     if path_obj.is_absolute() {
         path_obj.to_path_buf()
     } else if let Some(wd) = working_directory {
@@ -62,6 +104,20 @@ pub struct ReadFileParams {
         description = "The path to the file to read (absolute or relative to working directory)"
     )]
     pub path: String,
+    #[schemars(description = "Line number to start reading from (1-based, default: 1)")]
+    pub offset: Option<u32>,
+    #[schemars(description = "Maximum number of lines to return (default: 2000)")]
+    pub limit: Option<u32>,
+    #[schemars(description = "Maximum number of bytes to return (default: 262144)")]
+    pub max_bytes: Option<u32>,
+    #[schemars(
+        description = "Text encoding of the file, e.g. 'latin1' or 'shift_jis' (default: UTF-8)"
+    )]
+    pub encoding: Option<String>,
+    #[schemars(
+        description = "For images, downscale so neither side exceeds this many pixels; images too large to embed are downscaled regardless"
+    )]
+    pub max_image_dimension: Option<u32>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -74,8 +130,117 @@ pub struct WriteFileParams {
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct ExecuteCommandParams {
-    #[schemars(description = "The command to execute")]
+    #[schemars(description = "The shell command line to execute; pass this or program")]
+    pub command: Option<String>,
+    #[schemars(
+        description = "Shell that interprets command: 'sh', 'bash', 'zsh', 'pwsh' or 'cmd' (default: sh, or cmd on Windows)"
+    )]
+    pub shell: Option<Shell>,
+    #[schemars(
+        description = "Program to run directly without a shell, so args need no quoting; pass this or command"
+    )]
+    pub program: Option<String>,
+    #[schemars(description = "Arguments passed to program")]
+    pub args: Option<Vec<String>>,
+    #[schemars(
+        description = "Directory to run the command in (default: workspace root or server working directory)"
+    )]
+    pub working_directory: Option<String>,
+    #[schemars(description = "Environment variables to add or override")]
+    pub env: Option<HashMap<String, String>>,
+    #[schemars(
+        description = "Start from an empty environment instead of inheriting the server's (default: false)"
+    )]
+    pub env_clear: Option<bool>,
+    #[schemars(
+        description = "Text to write to the command's standard input; with pty, it is typed into the terminal"
+    )]
+    pub stdin: Option<String>,
+    #[schemars(
+        description = "Run the command under a pseudo-terminal, so it behaves as it would for a developer; escape sequences are stripped from the output and stderr is merged into stdout (default: false)"
+    )]
+    pub pty: Option<bool>,
+    #[schemars(description = "Terminal height in rows when pty is set (default: 24)")]
+    pub pty_rows: Option<u16>,
+    #[schemars(description = "Terminal width in columns when pty is set (default: 80)")]
+    pub pty_cols: Option<u16>,
+    #[schemars(
+        description = "Start the command in the background and return a process id instead of waiting for it (default: false)"
+    )]
+    pub background: Option<bool>,
+    #[schemars(
+        description = "Kill the command if it runs longer than this many milliseconds; 0 disables the timeout (default: server setting)"
+    )]
+    pub timeout_ms: Option<u64>,
+    #[schemars(
+        description = "Resource limits for the command; the server's own limits still apply when they are stricter"
+    )]
+    pub limits: Option<ResourceLimits>,
+    #[schemars(
+        description = "Maximum bytes of stdout and of stderr to return; the middle of longer output is omitted and the full text saved to a file. Background processes keep only this many unread bytes of each (default: server setting)"
+    )]
+    pub max_output_bytes: Option<usize>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ProcessOutputParams {
+    #[schemars(description = "The id returned when the background process was started")]
+    pub process_id: u32,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ProcessWriteStdinParams {
+    #[schemars(description = "The id returned when the background process was started")]
+    pub process_id: u32,
+    #[schemars(description = "Text to write to the process's standard input")]
+    pub input: String,
+    #[schemars(description = "Close standard input after writing (default: false)")]
+    pub close_stdin: Option<bool>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ProcessKillParams {
+    #[schemars(description = "The id returned when the background process was started")]
+    pub process_id: u32,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ProcessListParams {}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ShellSessionCreateParams {
+    #[schemars(description = "Shell to start: 'sh', 'bash' or 'zsh' (default: bash)")]
+    pub shell: Option<Shell>,
+    #[schemars(
+        description = "Directory the shell starts in (default: workspace root or server working directory)"
+    )]
+    pub working_directory: Option<String>,
+    #[schemars(description = "Environment variables to add or override")]
+    pub env: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ShellSessionRunParams {
+    #[schemars(description = "The id returned when the shell session was created")]
+    pub session_id: u32,
+    #[schemars(
+        description = "Command line to run; cd, export and source affect later commands in the session"
+    )]
     pub command: String,
+    #[schemars(
+        description = "Kill the session if the command runs longer than this many milliseconds; 0 disables the timeout (default: server setting)"
+    )]
+    pub timeout_ms: Option<u64>,
+    #[schemars(
+        description = "Maximum bytes of stdout and of stderr to return; the middle of longer output is omitted and the full text saved to a file (default: server setting)"
+    )]
+    pub max_output_bytes: Option<usize>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ShellSessionCloseParams {
+    #[schemars(description = "The id returned when the shell session was created")]
+    pub session_id: u32,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -128,18 +293,60 @@ pub struct NowParams {
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct SearchReplaceEditParams {
+    #[schemars(description = "The path to the file to edit; pass this with edits, or use files")]
+    pub path: Option<String>,
+    #[schemars(description = "List of search and replace operations for path")]
+    pub edits: Option<Vec<EditOperation>>,
+    #[schemars(
+        description = "Edits to several files, applied all together or not at all if any edit fails to match"
+    )]
+    pub files: Option<Vec<FileEdits>>,
+    #[schemars(
+        description = "Return the diff the edits would produce without changing any file (default: false)"
+    )]
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct EditLinesParams {
     #[schemars(description = "The path to the file to edit")]
     pub path: String,
-    #[schemars(description = "List of search and replace operations")]
-    pub edits: Vec<EditOperation>,
+    #[schemars(
+        description = "Operations addressed by the line numbers read_file shows, all referring to the file before any of them is applied; ranges may not overlap"
+    )]
+    pub operations: Vec<LineOperation>,
+    #[schemars(
+        description = "Return the diff the operations would produce without changing the file (default: false)"
+    )]
+    pub dry_run: Option<bool>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct ApplyPatchParams {
-    #[schemars(description = "The path to the file to patch")]
-    pub path: String,
-    #[schemars(description = "The unified diff patch content")]
+    #[schemars(
+        description = "The file to patch, in place of the paths the patch names. Required when the patch has no ---/+++ file headers; leave it out to apply a multi-file patch"
+    )]
+    pub path: Option<String>,
+    #[schemars(
+        description = "The unified diff patch content. May cover several files as produced by git diff, including created (/dev/null), deleted and renamed files and mode changes"
+    )]
     pub patch: String,
+    #[schemars(
+        description = "Directory the paths in the patch are relative to (default: the workspace root or current directory)"
+    )]
+    pub directory: Option<String>,
+    #[schemars(
+        description = "Number of leading path components to remove from the paths in the patch, as with patch -p (default: 1 for git-style a/ and b/ paths, 0 otherwise)"
+    )]
+    pub strip: Option<usize>,
+    #[schemars(
+        description = "Return the diff the patch would produce without changing the file (default: false)"
+    )]
+    pub dry_run: Option<bool>,
+    #[schemars(
+        description = "How many context lines at each end of a hunk may be ignored when it doesn't match exactly, as with patch --fuzz (default: 2)"
+    )]
+    pub fuzz: Option<usize>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
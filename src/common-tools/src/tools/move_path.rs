@@ -1,17 +1,18 @@
 use rmcp::model::{CallToolResult, Content};
 use tokio::fs;
 
-use super::{resolve_path, ToolError};
+use super::{ToolError, Workspace};
 
 pub struct MovePathTool {
     pub source_path: String,
     pub destination_path: String,
+    pub workspace: Workspace,
 }
 
 impl MovePathTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
-        let source_abs = resolve_path(&self.source_path, None);
-        let dest_abs = resolve_path(&self.destination_path, None);
+        let source_abs = self.workspace.resolve_write(&self.source_path)?;
+        let dest_abs = self.workspace.resolve_write(&self.destination_path)?;
 
         if !source_abs.exists() {
             return Err(ToolError::FileNotFound(source_abs.display().to_string()));
@@ -0,0 +1,336 @@
+use super::apply_patch::parse_hunk_header;
+
+/// File type bits of a git mode that the patch tool can't create: symlinks
+/// and submodules.
+const UNSUPPORTED_MODES: [(u32, &str); 2] = [(0o120000, "symlinks"), (0o160000, "submodules")];
+const FILE_TYPE_MASK: u32 = 0o170000;
+
+/// One file's part of a patch.
+pub(crate) struct FilePatch<'a> {
+    /// Where the file is before the patch, or `None` if the patch creates it.
+    pub(crate) old_path: Option<String>,
+    /// Where the file is after the patch, or `None` if the patch deletes it.
+    /// Both are `None` for hunks that come without file headers.
+    pub(crate) new_path: Option<String>,
+    /// Whether git marked the change as a rename, so `old_path` goes away.
+    pub(crate) rename: bool,
+    /// Permission bits the file ends up with, from git's mode headers.
+    pub(crate) new_mode: Option<u32>,
+    /// The file's hunks, from its first `@@` line on.
+    pub(crate) hunk_lines: Vec<&'a str>,
+}
+
+/// A file section as written, before its names are worked out.
+#[derive(Default)]
+struct Section<'a> {
+    git_paths: Option<(String, String)>,
+    /// From the `---` and `+++` lines; `Some(None)` stands for /dev/null.
+    old_header: Option<Option<String>>,
+    new_header: Option<Option<String>>,
+    rename_from: Option<String>,
+    rename_to: Option<String>,
+    new_file: bool,
+    deleted_file: bool,
+    new_mode: Option<String>,
+    binary: bool,
+    copy: bool,
+    hunk_lines: Vec<&'a str>,
+}
+
+/// Splits a unified diff into the files it changes, understanding the
+/// extended headers `git diff` writes for created, deleted and renamed files
+/// and mode changes.
+///
+/// `strip` removes that many leading components from the paths in `---`,
+/// `+++` and `diff --git` lines, like `patch -p`. Without it, git's `a/` and
+/// `b/` prefixes are removed and other paths are used as they are.
+pub(crate) fn parse_patch_set<'a>(
+    lines: &[&'a str],
+    strip: Option<usize>,
+) -> Result<Vec<FilePatch<'a>>, String> {
+    let mut sections: Vec<Section> = Vec::new();
+    // Old and new lines the current hunk's header says are still to come.
+    // Until they have, nothing is taken for a file header.
+    let mut owed = (0, 0);
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        if owed != (0, 0) {
+            owed = count_hunk_line(line, owed);
+            if let Some(section) = sections.last_mut() {
+                section.hunk_lines.push(line);
+            }
+            i += 1;
+            continue;
+        }
+
+        let current = sections.last_mut();
+        let in_header = current
+            .as_ref()
+            .is_some_and(|s| s.hunk_lines.is_empty() && s.old_header.is_none());
+
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            sections.push(Section {
+                git_paths: Some(split_git_paths(rest)?),
+                ..Default::default()
+            });
+        } else if line.starts_with("--- ")
+            && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "))
+        {
+            // A git section's own ---/+++ pair follows its extended headers.
+            let git_header = in_header && current.as_ref().is_some_and(|s| s.git_paths.is_some());
+            if !git_header {
+                sections.push(Section::default());
+            }
+            let section = sections.last_mut().expect("section was just pushed");
+            section.old_header = Some(header_path(&line[4..]));
+            section.new_header = Some(header_path(&lines[i + 1][4..]));
+            i += 1;
+        } else if let Some(section) = current.filter(|_| in_header) {
+            if let Some(path) = line.strip_prefix("rename from ") {
+                section.rename_from = Some(unquote(path));
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                section.rename_to = Some(unquote(path));
+            } else if let Some(mode) = line.strip_prefix("new file mode ") {
+                section.new_file = true;
+                section.new_mode = Some(mode.trim().to_string());
+            } else if line.starts_with("deleted file mode ") {
+                section.deleted_file = true;
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                section.new_mode = Some(mode.trim().to_string());
+            } else if line.starts_with("copy from ") || line.starts_with("copy to ") {
+                section.copy = true;
+            } else if line.starts_with("GIT binary patch") || line.starts_with("Binary files ") {
+                section.binary = true;
+            } else if line.starts_with("@@") {
+                section.hunk_lines.push(line);
+            }
+        } else if line.starts_with("@@") && sections.is_empty() {
+            // Hunks without any file headers.
+            sections.push(Section {
+                hunk_lines: vec![line],
+                ..Default::default()
+            });
+        } else if let Some(section) = sections.last_mut() {
+            if !section.hunk_lines.is_empty() || line.starts_with("@@") {
+                section.hunk_lines.push(line);
+            }
+        }
+        if line.starts_with("@@") {
+            // A malformed header is reported when the hunks are parsed.
+            owed = parse_hunk_header(line)
+                .map(|header| (header.old_count, header.new_count))
+                .unwrap_or_default();
+        }
+        i += 1;
+    }
+
+    sections
+        .into_iter()
+        .map(|section| section.into_file_patch(strip))
+        .collect()
+}
+
+/// What is owed to a hunk after `line`. A line that can't be part of a
+/// hunk ends it; parsing the hunk reports the mismatch.
+fn count_hunk_line(line: &str, (old, new): (usize, usize)) -> (usize, usize) {
+    match line.chars().next() {
+        Some(' ') | None => (old.saturating_sub(1), new.saturating_sub(1)),
+        Some('-') => (old.saturating_sub(1), new),
+        Some('+') => (old, new.saturating_sub(1)),
+        Some('\\') => (old, new),
+        _ if line.trim().is_empty() => (old.saturating_sub(1), new.saturating_sub(1)),
+        _ => (0, 0),
+    }
+}
+
+impl<'a> Section<'a> {
+    fn into_file_patch(self, strip: Option<usize>) -> Result<FilePatch<'a>, String> {
+        let name = self
+            .git_paths
+            .as_ref()
+            .map(|(_, new)| new.clone())
+            .or_else(|| self.new_header.clone().flatten())
+            .or_else(|| self.old_header.clone().flatten())
+            .unwrap_or_default();
+        if self.binary {
+            return Err(format!("{}: binary patches are not supported", name));
+        }
+        if self.copy {
+            return Err(format!("{}: copies are not supported", name));
+        }
+
+        // git's a/ and b/ prefixes go unless asked otherwise.
+        let strip = strip.unwrap_or_else(|| {
+            let prefixed = |header: &Option<Option<String>>, prefix: &str| match header {
+                Some(Some(path)) => path.starts_with(prefix),
+                _ => true,
+            };
+            let named = matches!(self.old_header, Some(Some(_)))
+                || matches!(self.new_header, Some(Some(_)));
+            let git_prefixes =
+                named && prefixed(&self.old_header, "a/") && prefixed(&self.new_header, "b/");
+            usize::from(self.git_paths.is_some() || git_prefixes)
+        });
+        let strip_header = |header: Option<Option<String>>| -> Result<_, String> {
+            header
+                .map(|path| path.map(|p| strip_components(&p, strip)).transpose())
+                .transpose()
+        };
+        let old_header = strip_header(self.old_header)?;
+        let new_header = strip_header(self.new_header)?;
+        let git_paths = match self.git_paths {
+            Some((old, new)) => Some((
+                strip_components(&old, strip)?,
+                strip_components(&new, strip)?,
+            )),
+            None => None,
+        };
+
+        let old_path = if self.new_file || old_header == Some(None) {
+            None
+        } else {
+            self.rename_from
+                .clone()
+                .or(old_header.flatten())
+                .or(git_paths.as_ref().map(|(old, _)| old.clone()))
+        };
+        let new_path = if self.deleted_file || new_header == Some(None) {
+            None
+        } else {
+            self.rename_to
+                .clone()
+                .or(new_header.flatten())
+                .or(git_paths.map(|(_, new)| new))
+        };
+        if old_path.is_none() && new_path.is_none() && !name.is_empty() {
+            return Err(format!(
+                "{}: the patch neither keeps nor creates the file",
+                name
+            ));
+        }
+
+        let new_mode = self
+            .new_mode
+            .map(|mode| parse_mode(&mode).map_err(|e| format!("{}: {}", name, e)))
+            .transpose()?;
+
+        Ok(FilePatch {
+            old_path,
+            new_path,
+            rename: self.rename_from.is_some() && self.rename_to.is_some(),
+            new_mode,
+            hunk_lines: self.hunk_lines,
+        })
+    }
+}
+
+/// Parses a git mode such as `100755` into its permission bits.
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| format!("invalid file mode {}", mode))?;
+    for (file_type, name) in UNSUPPORTED_MODES {
+        if mode & FILE_TYPE_MASK == file_type {
+            return Err(format!("{} are not supported", name));
+        }
+    }
+    Ok(mode & 0o7777)
+}
+
+/// The path in a `---` or `+++` line, without any timestamp after it.
+/// `None` means /dev/null.
+fn header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim_end();
+    let path = unquote(path);
+    (path != "/dev/null").then_some(path)
+}
+
+/// Splits the two paths of a `diff --git a/x b/x` line.
+fn split_git_paths(paths: &str) -> Result<(String, String), String> {
+    if paths.starts_with('"') {
+        if let Some(end) = closing_quote(paths) {
+            let (old, new) = paths.split_at(end + 1);
+            return Ok((unquote(old), unquote(new.trim_start())));
+        }
+    }
+
+    // Unquoted paths may contain spaces; both halves name the same file
+    // unless it is renamed, so try the middle first.
+    let middle = paths.len() / 2;
+    if paths.len() % 2 == 1 && paths.as_bytes()[middle] == b' ' {
+        let (old, new) = (&paths[..middle], &paths[middle + 1..]);
+        if old.split_once('/').map(|(_, p)| p) == new.split_once('/').map(|(_, p)| p) {
+            return Ok((old.to_string(), new.to_string()));
+        }
+    }
+    match paths.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [old, new] => Ok((unquote(old), unquote(new))),
+        _ => Err(format!(
+            "Cannot tell the paths apart in: diff --git {}",
+            paths
+        )),
+    }
+}
+
+fn closing_quote(quoted: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in quoted.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Undoes git's quoting of paths with unusual characters.
+fn unquote(path: &str) -> String {
+    let Some(inner) = path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) else {
+        return path.to_string();
+    };
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.bytes().peekable();
+    while let Some(b) = chars.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match chars.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(d @ b'0'..=b'7') => {
+                // Octal escapes spell out UTF-8 bytes.
+                let mut value = u32::from(d - b'0');
+                for _ in 0..2 {
+                    if let Some(d @ b'0'..=b'7') = chars.peek().copied() {
+                        value = value * 8 + u32::from(d - b'0');
+                        chars.next();
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            Some(other) => bytes.push(other),
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Removes the first `count` components of `path`, like `patch -p`.
+fn strip_components(path: &str, count: usize) -> Result<String, String> {
+    let mut rest = path;
+    for _ in 0..count {
+        match rest.split_once('/') {
+            Some((_, tail)) => rest = tail.trim_start_matches('/'),
+            None => {
+                return Err(format!(
+                    "{} has fewer than {} leading directories to strip",
+                    path, count
+                ))
+            }
+        }
+    }
+    Ok(rest.to_string())
+}
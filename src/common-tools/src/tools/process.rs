@@ -0,0 +1,578 @@
+use chrono::{DateTime, Local};
+use std::collections::{BTreeMap, VecDeque};
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::{NamedTempFile, TempPath};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::ToolError;
+
+/// How long to keep draining pipes after the child exits, in case a detached
+/// grandchild is still holding them open.
+const OUTPUT_DRAIN_GRACE: Duration = Duration::from_secs(2);
+
+/// How long `kill` waits for a background process to go away.
+const KILL_WAIT: Duration = Duration::from_secs(5);
+
+/// Collects a child's output in the background so whatever was written is
+/// still available if the process has to be killed.
+pub(crate) struct CapturedOutput {
+    buffer: Arc<Mutex<OutputBuffer>>,
+    reader: Option<JoinHandle<()>>,
+    /// The file every byte is also written to, kept only if the output turns
+    /// out too long to return.
+    spill: Option<TempPath>,
+}
+
+/// Captured bytes not yet taken. Only the most recent `limit` bytes are
+/// kept, from the first character boundary within them; the ones dropped to
+/// stay within it are counted.
+#[derive(Default)]
+struct OutputBuffer {
+    bytes: VecDeque<u8>,
+    limit: usize,
+    /// Total bytes captured before the first one still in `bytes`.
+    start: usize,
+    /// Bytes dropped to stay within the limit since the last `take_new`.
+    dropped: usize,
+    /// The first `head_limit` bytes captured, kept apart from the tail.
+    head: Vec<u8>,
+    head_limit: usize,
+    /// Line breaks captured in all.
+    newlines: usize,
+    /// Writing the spill file failed, so it doesn't hold everything.
+    spill_failed: bool,
+}
+
+impl OutputBuffer {
+    fn push(&mut self, chunk: &[u8]) {
+        let room = self.head_limit.saturating_sub(self.head.len());
+        self.head.extend_from_slice(&chunk[..room.min(chunk.len())]);
+        self.newlines += chunk.iter().filter(|&&b| b == b'\n').count();
+
+        self.bytes.extend(chunk);
+        let mut excess = self.bytes.len().saturating_sub(self.limit);
+        if excess > 0 {
+            excess += self
+                .bytes
+                .range(excess..)
+                .take_while(|&&b| is_continuation(b))
+                .count();
+        }
+        self.bytes.drain(..excess);
+        self.start += excess;
+        self.dropped += excess;
+    }
+
+    fn total(&self) -> usize {
+        self.start + self.bytes.len()
+    }
+
+    /// How many buffered bytes can be decoded now: all of them once the
+    /// output has ended, otherwise all but a character cut off at the end,
+    /// whose remaining bytes are still to come.
+    fn readable(&mut self, ended: bool) -> usize {
+        let bytes = self.bytes.make_contiguous();
+        if ended {
+            bytes.len()
+        } else {
+            complete_len(bytes)
+        }
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// The length of `bytes` without a UTF-8 sequence cut off at the end.
+fn complete_len(bytes: &[u8]) -> usize {
+    for (i, &byte) in bytes.iter().rev().take(4).enumerate() {
+        if is_continuation(byte) {
+            continue;
+        }
+        let width = match byte {
+            0xF0.. => 4,
+            0xE0.. => 3,
+            0xC0.. => 2,
+            _ => 1,
+        };
+        return if width > i + 1 {
+            bytes.len() - i - 1
+        } else {
+            bytes.len()
+        };
+    }
+    bytes.len()
+}
+
+impl CapturedOutput {
+    /// Starts reading a background process's `pipe`, keeping only the last
+    /// `limit` bytes not yet taken.
+    pub(crate) fn capture<R>(pipe: Option<R>, limit: usize) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let buffer = OutputBuffer {
+            limit,
+            ..Default::default()
+        };
+        Self::start(pipe, buffer, None)
+    }
+
+    /// Starts reading a command's `pipe` for `finish_capped`. Memory holds
+    /// only the first and last `max_bytes / 2`, so every byte is also written
+    /// to `spill` as it arrives.
+    pub(crate) fn capture_spilled<R>(
+        pipe: Option<R>,
+        max_bytes: usize,
+        spill: Option<NamedTempFile>,
+    ) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let buffer = OutputBuffer {
+            limit: max_bytes,
+            head_limit: max_bytes / 2,
+            ..Default::default()
+        };
+        Self::start(pipe, buffer, spill)
+    }
+
+    fn start<R>(pipe: Option<R>, buffer: OutputBuffer, spill: Option<NamedTempFile>) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let buffer = Arc::new(Mutex::new(buffer));
+        let (spill_file, spill) = match spill {
+            Some(spill) => {
+                let (file, path) = spill.into_parts();
+                (Some(tokio::fs::File::from_std(file)), Some(path))
+            }
+            None => (None, None),
+        };
+
+        let reader = pipe.map(|mut pipe| {
+            let buffer = Arc::clone(&buffer);
+            let mut spill_file = spill_file;
+            tokio::spawn(async move {
+                let mut chunk = [0u8; 8192];
+                while let Ok(n) = pipe.read(&mut chunk).await {
+                    if n == 0 {
+                        break;
+                    }
+                    if let Some(file) = &mut spill_file {
+                        if file.write_all(&chunk[..n]).await.is_err() {
+                            spill_file = None;
+                            buffer.lock().unwrap().spill_failed = true;
+                        }
+                    }
+                    buffer.lock().unwrap().push(&chunk[..n]);
+                }
+                if let Some(mut file) = spill_file {
+                    if file.flush().await.is_err() {
+                        buffer.lock().unwrap().spill_failed = true;
+                    }
+                }
+            })
+        });
+
+        Self {
+            buffer,
+            reader,
+            spill,
+        }
+    }
+
+    /// Whether the pipe has been read to its end.
+    fn ended(&self) -> bool {
+        self.reader
+            .as_ref()
+            .is_none_or(|reader| reader.is_finished())
+    }
+
+    /// Removes and returns everything captured since the last call, noting
+    /// how many bytes were dropped in between to stay within the limit. A
+    /// character whose bytes haven't all arrived is left for the next call.
+    pub(crate) fn take_new(&self) -> String {
+        let ended = self.ended();
+        let mut buffer = self.buffer.lock().unwrap();
+        let readable = buffer.readable(ended);
+        let bytes: Vec<u8> = buffer.bytes.drain(..readable).collect();
+        buffer.start += bytes.len();
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+
+        match std::mem::take(&mut buffer.dropped) {
+            0 => text,
+            dropped => format!("… {} earlier bytes dropped …\n{}", dropped, text),
+        }
+    }
+
+    /// Returns what was captured after the first `offset` bytes, along with
+    /// the offset to pass next time. Unlike `take_new`, nothing is removed,
+    /// but a character whose bytes haven't all arrived is likewise held back.
+    pub(crate) fn read_since(&self, offset: usize) -> (String, usize) {
+        let ended = self.ended();
+        let mut buffer = self.buffer.lock().unwrap();
+        let readable = buffer.readable(ended);
+        let start = offset.saturating_sub(buffer.start).min(readable);
+        let bytes: Vec<u8> = buffer.bytes.range(start..readable).copied().collect();
+        (
+            String::from_utf8_lossy(&bytes).into_owned(),
+            buffer.start + readable,
+        )
+    }
+
+    /// Waits for the output to end. Output within the limit is returned
+    /// whole; longer output as its first and last lines, along with the path
+    /// of the spill file holding all of it. `render` turns raw bytes into the
+    /// text returned.
+    pub(crate) async fn finish_capped(
+        mut self,
+        render: impl Fn(String) -> String,
+    ) -> (String, Option<String>) {
+        if let Some(mut reader) = self.reader.take() {
+            if tokio::time::timeout(OUTPUT_DRAIN_GRACE, &mut reader)
+                .await
+                .is_err()
+            {
+                reader.abort();
+            }
+        }
+
+        let buffer = self.buffer.lock().unwrap();
+        let lossy = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        if buffer.total() <= buffer.limit {
+            let bytes: Vec<u8> = buffer.bytes.iter().copied().collect();
+            return (render(lossy(&bytes)), None);
+        }
+
+        let head = &buffer.head[..complete_len(&buffer.head)];
+        let tail_len = buffer.head_limit.min(buffer.bytes.len());
+        let tail: Vec<u8> = buffer
+            .bytes
+            .range(buffer.bytes.len() - tail_len..)
+            .copied()
+            .skip_while(|&b| is_continuation(b))
+            .collect();
+        let text = join_head_tail(&render(lossy(head)), &render(lossy(&tail)), buffer.newlines);
+
+        let spill = self.spill.take().filter(|_| !buffer.spill_failed);
+        let file = spill
+            .and_then(|path| path.keep().ok())
+            .map(|path| path.display().to_string());
+        (text, file)
+    }
+}
+
+pub(crate) fn combine_output(stdout: &str, stderr: &str) -> String {
+    let mut result = String::new();
+    if !stdout.is_empty() {
+        result.push_str(stdout);
+    }
+    if !stderr.is_empty() {
+        if !result.is_empty() {
+            result.push_str("\n--- STDERR ---\n");
+        }
+        result.push_str(stderr);
+    }
+    result
+}
+
+/// Keeps the first and last `max_bytes / 2` bytes of `text`, cut at line
+/// boundaries where possible. Returns `None` if `text` already fits.
+pub(crate) fn truncate_head_tail(text: &str, max_bytes: usize) -> Option<String> {
+    if text.len() <= max_bytes {
+        return None;
+    }
+
+    let half = max_bytes / 2;
+
+    let mut head_end = half;
+    while !text.is_char_boundary(head_end) {
+        head_end -= 1;
+    }
+    let mut tail_start = text.len() - half;
+    while !text.is_char_boundary(tail_start) {
+        tail_start += 1;
+    }
+
+    Some(join_head_tail(
+        &text[..head_end],
+        &text[tail_start..],
+        text.matches('\n').count(),
+    ))
+}
+
+/// Joins the start and end of a text with `newlines` line breaks in all,
+/// cut at line boundaries where possible, around a note of how many lines
+/// were left out.
+fn join_head_tail(head: &str, tail: &str, newlines: usize) -> String {
+    let head = match head.rfind('\n') {
+        Some(newline) => &head[..newline + 1],
+        None => head,
+    };
+    let tail = match tail.find('\n') {
+        Some(newline) if newline + 1 < tail.len() => &tail[newline + 1..],
+        _ => tail,
+    };
+
+    let omitted_lines = newlines
+        .saturating_sub(head.matches('\n').count())
+        .saturating_sub(tail.matches('\n').count());
+    format!("{}… {} lines omitted …\n{}", head, omitted_lines, tail)
+}
+
+pub(crate) async fn kill_process_group(child: &mut Child) -> Option<ExitStatus> {
+    if let Some(pid) = child.id() {
+        signal_process_group(pid);
+    }
+
+    let _ = child.start_kill();
+    child.wait().await.ok()
+}
+
+pub(crate) fn signal_process_group(_pid: u32) {
+    // SAFETY: killpg has no memory-safety preconditions; spawned commands lead
+    // their own process group because of `process_group(0)`.
+    #[cfg(unix)]
+    unsafe {
+        libc::killpg(_pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessStatus {
+    Running,
+    Exited(Option<i32>),
+}
+
+impl std::fmt::Display for ProcessStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessStatus::Running => write!(f, "running"),
+            ProcessStatus::Exited(Some(code)) => write!(f, "exited with code {}", code),
+            ProcessStatus::Exited(None) => write!(f, "terminated by signal"),
+        }
+    }
+}
+
+impl From<Option<ExitStatus>> for ProcessStatus {
+    fn from(status: Option<ExitStatus>) -> Self {
+        ProcessStatus::Exited(status.and_then(|s| s.code()))
+    }
+}
+
+struct BackgroundProcess {
+    command: String,
+    pid: Option<u32>,
+    started_at: DateTime<Local>,
+    stdout: CapturedOutput,
+    stderr: CapturedOutput,
+    stdin: Option<Arc<tokio::sync::Mutex<ChildStdin>>>,
+    status: watch::Receiver<ProcessStatus>,
+    kill: CancellationToken,
+}
+
+impl Drop for BackgroundProcess {
+    fn drop(&mut self) {
+        if *self.status.borrow() == ProcessStatus::Running {
+            if let Some(pid) = self.pid {
+                signal_process_group(pid);
+            }
+        }
+        self.kill.cancel();
+    }
+}
+
+/// Output collected from a background process since it was last polled.
+pub struct ProcessOutput {
+    pub status: ProcessStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+pub struct ProcessSummary {
+    pub id: u32,
+    pub pid: Option<u32>,
+    pub command: String,
+    pub started_at: DateTime<Local>,
+    pub status: ProcessStatus,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u32,
+    processes: BTreeMap<u32, BackgroundProcess>,
+}
+
+/// Background processes started by one MCP session.
+///
+/// Clones share the same processes. Once the last clone is dropped, which
+/// happens when the session ends, every process still running is killed.
+#[derive(Clone, Default)]
+pub struct ProcessRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl ProcessRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes ownership of a spawned child and returns its handle. The child
+    /// is killed if it is still running after `wall_time`. Of the output not
+    /// yet read, only the last `max_output_bytes` of each stream are kept.
+    pub(crate) fn register(
+        &self,
+        command: &str,
+        mut child: Child,
+        wall_time: Option<Duration>,
+        max_output_bytes: usize,
+    ) -> u32 {
+        let pid = child.id();
+        let stdout = CapturedOutput::capture(child.stdout.take(), max_output_bytes);
+        let stderr = CapturedOutput::capture(child.stderr.take(), max_output_bytes);
+        let stdin = child
+            .stdin
+            .take()
+            .map(|pipe| Arc::new(tokio::sync::Mutex::new(pipe)));
+
+        let (status_tx, status_rx) = watch::channel(ProcessStatus::Running);
+        let kill = CancellationToken::new();
+
+        tokio::spawn({
+            let kill = kill.clone();
+            async move {
+                let deadline = async {
+                    match wall_time {
+                        Some(wall_time) => tokio::time::sleep(wall_time).await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                let status = tokio::select! {
+                    status = child.wait() => status.ok(),
+                    _ = kill.cancelled() => kill_process_group(&mut child).await,
+                    _ = deadline => kill_process_group(&mut child).await,
+                };
+                let _ = status_tx.send(status.into());
+            }
+        });
+
+        let mut registry = self.inner.lock().unwrap();
+        registry.next_id += 1;
+        let id = registry.next_id;
+        registry.processes.insert(
+            id,
+            BackgroundProcess {
+                command: command.to_string(),
+                pid,
+                started_at: Local::now(),
+                stdout,
+                stderr,
+                stdin,
+                status: status_rx,
+                kill,
+            },
+        );
+
+        id
+    }
+
+    pub fn read_output(&self, id: u32) -> Result<ProcessOutput, ToolError> {
+        let registry = self.inner.lock().unwrap();
+        let process = registry
+            .processes
+            .get(&id)
+            .ok_or_else(|| unknown_process(id))?;
+
+        let status = *process.status.borrow();
+        Ok(ProcessOutput {
+            status,
+            stdout: process.stdout.take_new(),
+            stderr: process.stderr.take_new(),
+        })
+    }
+
+    pub async fn write_stdin(&self, id: u32, input: &str, close: bool) -> Result<(), ToolError> {
+        let stdin = {
+            let mut registry = self.inner.lock().unwrap();
+            let process = registry
+                .processes
+                .get_mut(&id)
+                .ok_or_else(|| unknown_process(id))?;
+
+            if close {
+                process.stdin.take()
+            } else {
+                process.stdin.clone()
+            }
+        };
+
+        let stdin = stdin.ok_or_else(|| {
+            ToolError::InvalidArgument(format!("Standard input of process {} is closed", id))
+        })?;
+
+        let mut pipe = stdin.lock().await;
+        pipe.write_all(input.as_bytes()).await?;
+        pipe.flush().await?;
+        Ok(())
+    }
+
+    /// Kills the process group, waits for it to exit and forgets the process,
+    /// returning whatever output had not been read yet.
+    pub async fn kill(&self, id: u32) -> Result<ProcessOutput, ToolError> {
+        let mut status = {
+            let registry = self.inner.lock().unwrap();
+            let process = registry
+                .processes
+                .get(&id)
+                .ok_or_else(|| unknown_process(id))?;
+            process.kill.cancel();
+            process.status.clone()
+        };
+
+        let _ = tokio::time::timeout(KILL_WAIT, status.wait_for(|s| *s != ProcessStatus::Running))
+            .await;
+
+        let process = self
+            .inner
+            .lock()
+            .unwrap()
+            .processes
+            .remove(&id)
+            .ok_or_else(|| unknown_process(id))?;
+
+        let status = *process.status.borrow();
+        Ok(ProcessOutput {
+            status,
+            stdout: process.stdout.take_new(),
+            stderr: process.stderr.take_new(),
+        })
+    }
+
+    pub fn list(&self) -> Vec<ProcessSummary> {
+        let registry = self.inner.lock().unwrap();
+        registry
+            .processes
+            .iter()
+            .map(|(&id, process)| ProcessSummary {
+                id,
+                pid: process.pid,
+                command: process.command.clone(),
+                started_at: process.started_at,
+                status: *process.status.borrow(),
+            })
+            .collect()
+    }
+}
+
+fn unknown_process(id: u32) -> ToolError {
+    ToolError::InvalidArgument(format!("No background process with id {}", id))
+}
@@ -0,0 +1,23 @@
+use rmcp::model::{CallToolResult, Content};
+
+use super::process::{combine_output, ProcessRegistry};
+use super::ToolError;
+
+pub struct ProcessKillTool {
+    pub process_id: u32,
+    pub processes: ProcessRegistry,
+}
+
+impl ProcessKillTool {
+    pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
+        let output = self.processes.kill(self.process_id).await?;
+        let remaining_output = combine_output(&output.stdout, &output.stderr);
+
+        let mut message = format!("Killed process {} ({})", self.process_id, output.status);
+        if !remaining_output.is_empty() {
+            message.push_str(&format!(", unread output:\n{}", remaining_output));
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(message)]))
+    }
+}
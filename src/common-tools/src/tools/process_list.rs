@@ -0,0 +1,42 @@
+use rmcp::model::{CallToolResult, Content};
+
+use super::process::ProcessRegistry;
+use super::ToolError;
+
+pub struct ProcessListTool {
+    pub processes: ProcessRegistry,
+}
+
+impl ProcessListTool {
+    pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
+        let processes = self.processes.list();
+
+        if processes.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No background processes",
+            )]));
+        }
+
+        let lines: Vec<String> = processes
+            .iter()
+            .map(|p| {
+                format!(
+                    "[{}] pid {}, {}, started {}: {}",
+                    p.id,
+                    p.pid
+                        .map_or_else(|| "unknown".to_string(), |pid| pid.to_string()),
+                    p.status,
+                    p.started_at.to_rfc3339(),
+                    p.command
+                )
+            })
+            .collect();
+
+        let message = format!(
+            "{} background processes:\n{}",
+            processes.len(),
+            lines.join("\n")
+        );
+        Ok(CallToolResult::success(vec![Content::text(message)]))
+    }
+}
@@ -0,0 +1,30 @@
+use rmcp::model::{CallToolResult, Content};
+
+use super::process::{combine_output, ProcessRegistry};
+use super::ToolError;
+
+pub struct ProcessOutputTool {
+    pub process_id: u32,
+    pub processes: ProcessRegistry,
+}
+
+impl ProcessOutputTool {
+    pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
+        let output = self.processes.read_output(self.process_id)?;
+        let new_output = combine_output(&output.stdout, &output.stderr);
+
+        let message = if new_output.is_empty() {
+            format!(
+                "Process {} is {} (no new output)",
+                self.process_id, output.status
+            )
+        } else {
+            format!(
+                "Process {} is {}, new output:\n{}",
+                self.process_id, output.status, new_output
+            )
+        };
+
+        Ok(CallToolResult::success(vec![Content::text(message)]))
+    }
+}
@@ -0,0 +1,30 @@
+use rmcp::model::{CallToolResult, Content};
+
+use super::process::ProcessRegistry;
+use super::ToolError;
+
+pub struct ProcessWriteStdinTool {
+    pub process_id: u32,
+    pub input: String,
+    pub close_stdin: bool,
+    pub processes: ProcessRegistry,
+}
+
+impl ProcessWriteStdinTool {
+    pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
+        self.processes
+            .write_stdin(self.process_id, &self.input, self.close_stdin)
+            .await?;
+
+        let mut message = format!(
+            "Wrote {} bytes to process {}",
+            self.input.len(),
+            self.process_id
+        );
+        if self.close_stdin {
+            message.push_str(" and closed its standard input");
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(message)]))
+    }
+}
@@ -0,0 +1,305 @@
+/// Terminal dimensions reported to a command run under a pseudo-terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtySize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl Default for PtySize {
+    fn default() -> Self {
+        Self { rows: 24, cols: 80 }
+    }
+}
+
+/// Renders raw terminal output as plain text.
+///
+/// Escape sequences (colors, cursor movement, window titles) are removed,
+/// carriage returns and backspaces overwrite what is already on the line the
+/// way they would on screen, so progress bars collapse to their final state,
+/// and CRLF line endings become LF.
+pub(crate) fn render_terminal_output(raw: &str) -> String {
+    let mut output = String::with_capacity(raw.len());
+    let mut line: Vec<char> = Vec::new();
+    let mut cursor: usize = 0;
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                output.extend(line.drain(..));
+                output.push('\n');
+                cursor = 0;
+            }
+            '\r' => cursor = 0,
+            '\x08' => cursor = cursor.saturating_sub(1),
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates, then a final byte.
+                Some('[') => {
+                    let mut params = String::new();
+                    let mut last = None;
+                    for next in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&next) {
+                            last = Some(next);
+                            break;
+                        }
+                        params.push(next);
+                    }
+                    if last == Some('K') {
+                        match params.as_str() {
+                            "" | "0" => line.truncate(cursor),
+                            "2" => {
+                                line.clear();
+                                cursor = 0;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                // OSC and other strings, ended by BEL or ST.
+                Some(']' | 'P' | '_' | '^') => {
+                    while let Some(next) = chars.next() {
+                        if next == '\x07' {
+                            break;
+                        }
+                        if next == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // Character set selection takes one more byte.
+                Some('(' | ')' | '*' | '+') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\t' => {
+                put_char(&mut line, &mut cursor, '\t');
+            }
+            c if c.is_control() => {}
+            c => put_char(&mut line, &mut cursor, c),
+        }
+    }
+
+    output.extend(line);
+    output
+}
+
+fn put_char(line: &mut Vec<char>, cursor: &mut usize, c: char) {
+    if *cursor < line.len() {
+        line[*cursor] = c;
+    } else {
+        line.push(c);
+    }
+    *cursor += 1;
+}
+
+#[cfg(unix)]
+pub(crate) use unix::Pty;
+
+#[cfg(not(unix))]
+pub(crate) use unsupported::Pty;
+
+#[cfg(not(unix))]
+mod unsupported {
+    use std::io;
+    use tokio::process::Command;
+
+    use super::PtySize;
+
+    /// Pseudo-terminals are only implemented on Unix; this can never be built.
+    #[derive(Clone)]
+    pub(crate) enum Pty {}
+
+    impl Pty {
+        pub(crate) fn attach(_cmd: &mut Command, _size: PtySize) -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "pseudo-terminals are only supported on Unix",
+            ))
+        }
+
+        pub(crate) fn reader(&self) -> tokio::io::Empty {
+            match *self {}
+        }
+
+        pub(crate) async fn write_all(&self, _input: &[u8]) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::pin::Pin;
+    use std::process::Stdio;
+    use std::sync::Arc;
+    use std::task::{ready, Context, Poll};
+    use tokio::io::unix::AsyncFd;
+    use tokio::io::{AsyncRead, ReadBuf};
+    use tokio::process::Command;
+
+    use super::PtySize;
+
+    /// The controlling side of a pseudo-terminal whose other side is a
+    /// command's stdin, stdout and stderr.
+    #[derive(Clone)]
+    pub(crate) struct Pty {
+        master: Arc<AsyncFd<OwnedFd>>,
+    }
+
+    impl Pty {
+        /// Opens a pseudo-terminal and makes it the controlling terminal and
+        /// standard streams of `cmd`. The command is put in a new session,
+        /// which also makes it the leader of its own process group, so it
+        /// must not be given a process group of its own.
+        pub(crate) fn attach(cmd: &mut Command, size: PtySize) -> io::Result<Self> {
+            let mut master: RawFd = -1;
+            let mut slave: RawFd = -1;
+            let winsize = libc::winsize {
+                ws_row: size.rows,
+                ws_col: size.cols,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+
+            // SAFETY: the out-pointers are valid for writes and a null name
+            // and termios are allowed.
+            let result = unsafe {
+                libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    // Some platforms declare this pointer mutable.
+                    std::ptr::from_ref(&winsize).cast_mut(),
+                )
+            };
+            if result != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // SAFETY: openpty succeeded, so both descriptors are open and
+            // owned by nobody else.
+            let (master, slave) =
+                unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+            set_flags(&master, libc::FD_CLOEXEC, Some(libc::O_NONBLOCK))?;
+            set_flags(&slave, libc::FD_CLOEXEC, None)?;
+
+            cmd.stdin(Stdio::from(slave.try_clone()?))
+                .stdout(Stdio::from(slave.try_clone()?))
+                .stderr(Stdio::from(slave));
+
+            // SAFETY: only async-signal-safe functions are called between
+            // fork and exec.
+            unsafe {
+                cmd.pre_exec(|| {
+                    if libc::setsid() == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+
+            Ok(Self {
+                master: Arc::new(AsyncFd::new(master)?),
+            })
+        }
+
+        pub(crate) fn reader(&self) -> PtyReader {
+            PtyReader {
+                master: Arc::clone(&self.master),
+            }
+        }
+
+        /// Types `input` into the terminal.
+        pub(crate) async fn write_all(&self, mut input: &[u8]) -> io::Result<()> {
+            while !input.is_empty() {
+                let mut guard = self.master.writable().await?;
+                match guard.try_io(|fd| {
+                    // SAFETY: `input` is valid for reads of its length.
+                    let n =
+                        unsafe { libc::write(fd.as_raw_fd(), input.as_ptr().cast(), input.len()) };
+                    if n < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(n as usize)
+                    }
+                }) {
+                    Ok(Ok(n)) => input = &input[n..],
+                    Ok(Err(e)) => return Err(e),
+                    Err(_would_block) => continue,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Reads what the command writes to the terminal. Reaches end of file
+    /// once every process holding the terminal open has exited.
+    pub(crate) struct PtyReader {
+        master: Arc<AsyncFd<OwnedFd>>,
+    }
+
+    impl AsyncRead for PtyReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            loop {
+                let mut guard = ready!(self.master.poll_read_ready(cx))?;
+                let unfilled = buf.initialize_unfilled();
+                match guard.try_io(|fd| {
+                    // SAFETY: `unfilled` is valid for writes of its length.
+                    let n = unsafe {
+                        libc::read(fd.as_raw_fd(), unfilled.as_mut_ptr().cast(), unfilled.len())
+                    };
+                    if n < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(n as usize)
+                    }
+                }) {
+                    Ok(Ok(n)) => {
+                        buf.advance(n);
+                        return Poll::Ready(Ok(()));
+                    }
+                    // Linux reports a hung-up terminal as EIO rather than EOF.
+                    Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => {
+                        return Poll::Ready(Ok(()))
+                    }
+                    Ok(Err(e)) => return Poll::Ready(Err(e)),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+    }
+
+    fn set_flags(
+        fd: &OwnedFd,
+        fd_flags: libc::c_int,
+        status_flags: Option<libc::c_int>,
+    ) -> io::Result<()> {
+        let fd = fd.as_raw_fd();
+        // SAFETY: fcntl on an open descriptor has no memory-safety
+        // preconditions.
+        unsafe {
+            if libc::fcntl(fd, libc::F_SETFD, fd_flags) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(flags) = status_flags {
+                let current = libc::fcntl(fd, libc::F_GETFL);
+                if current == -1 || libc::fcntl(fd, libc::F_SETFL, current | flags) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+}
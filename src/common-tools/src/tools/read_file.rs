@@ -1,15 +1,34 @@
-use rmcp::model::{CallToolResult, Content};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use image::{imageops::FilterType, ImageFormat, ImageReader};
+use rmcp::model::{CallToolResult, Content, ResourceContents};
+use std::io::Cursor;
+use std::path::Path;
 use tokio::fs;
 
-use super::{resolve_path, ToolError};
+use super::{file_type::FileType, ToolError, Workspace};
+
+pub const DEFAULT_LINE_LIMIT: u32 = 2000;
+pub const DEFAULT_MAX_BYTES: u32 = 256 * 1024;
+pub const MAX_EMBEDDED_BYTES: usize = 5 * 1024 * 1024;
+
+const LINE_NUMBER_WIDTH: usize = 6;
+const HEX_DUMP_BYTES: usize = 256;
+const HEX_DUMP_ROW_WIDTH: usize = 16;
+const DEFAULT_DOWNSCALE_DIMENSION: u32 = 1568;
 
 pub struct ReadFileTool {
     pub path: String,
+    pub offset: u32,
+    pub limit: u32,
+    pub max_bytes: u32,
+    pub encoding: Option<String>,
+    pub max_image_dimension: Option<u32>,
+    pub workspace: Workspace,
 }
 
 impl ReadFileTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
-        let abs_path = resolve_path(&self.path, None);
+        let abs_path = self.workspace.resolve_read(&self.path)?;
 
         if !abs_path.exists() {
             return Err(ToolError::FileNotFound(abs_path.display().to_string()));
@@ -22,7 +41,7 @@ impl ReadFileTool {
             )));
         }
 
-        let content = fs::read_to_string(&abs_path).await.map_err(|e| {
+        let bytes = fs::read(&abs_path).await.map_err(|e| {
             if e.kind() == std::io::ErrorKind::PermissionDenied {
                 ToolError::PermissionDenied(abs_path.display().to_string())
             } else {
@@ -30,6 +49,310 @@ impl ReadFileTool {
             }
         })?;
 
-        Ok(CallToolResult::success(vec![Content::text(content)]))
+        if self.encoding.is_none() {
+            match FileType::sniff_mime(&bytes) {
+                Some(mime @ ("image/png" | "image/jpeg" | "image/gif" | "image/webp")) => {
+                    return self.image_result(&abs_path, bytes, mime).await;
+                }
+                Some("application/pdf") => return pdf_result(&abs_path, &bytes),
+                _ => {}
+            }
+        }
+
+        let output = match self.decode(&bytes)? {
+            Decoded::Text(content) => self.format_page(&content)?,
+            Decoded::Lossy(content) => format!(
+                "Note: file is not valid UTF-8 and was decoded lossily \
+                 (pass encoding, e.g. \"latin1\", to decode it properly)\n{}",
+                self.format_page(&content)?
+            ),
+            Decoded::Binary => format_binary_summary(&abs_path, &bytes),
+        };
+
+        Ok(CallToolResult::success(vec![Content::text(output)]))
+    }
+
+    async fn image_result(
+        &self,
+        path: &Path,
+        bytes: Vec<u8>,
+        mime: &'static str,
+    ) -> Result<CallToolResult, ToolError> {
+        let max_dimension = self.max_image_dimension;
+        let image = tokio::task::spawn_blocking(move || prepare_image(bytes, mime, max_dimension))
+            .await
+            .map_err(|e| ToolError::Other(format!("Image processing failed: {}", e)))??;
+
+        if image.bytes.len() > MAX_EMBEDDED_BYTES {
+            return Err(ToolError::InvalidArgument(format!(
+                "Image is {} bytes, which exceeds the {} byte limit; \
+                 pass a smaller max_image_dimension to downscale it further",
+                image.bytes.len(),
+                MAX_EMBEDDED_BYTES
+            )));
+        }
+
+        let mut summary = format!(
+            "Image: {} ({}x{}, {}, {} bytes)",
+            path.display(),
+            image.width,
+            image.height,
+            image.mime,
+            image.bytes.len()
+        );
+        if let Some((width, height)) = image.original_size {
+            summary.push_str(&format!(", downscaled from {}x{}", width, height));
+        }
+
+        Ok(CallToolResult::success(vec![
+            Content::text(summary),
+            Content::image(BASE64.encode(&image.bytes), image.mime),
+        ]))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Decoded, ToolError> {
+        if let Some(label) = &self.encoding {
+            let encoding =
+                encoding_rs::Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| {
+                    ToolError::InvalidArgument(format!("Unknown encoding: {}", label))
+                })?;
+            let (content, _, _) = encoding.decode(bytes);
+            return Ok(Decoded::Text(content.into_owned()));
+        }
+
+        let (content, is_lossy) = match std::str::from_utf8(bytes) {
+            Ok(content) => (content.to_string(), false),
+            Err(_) => (String::from_utf8_lossy(bytes).into_owned(), true),
+        };
+
+        if !FileType::is_likely_utf8_text(&content) {
+            Ok(Decoded::Binary)
+        } else if is_lossy {
+            Ok(Decoded::Lossy(content))
+        } else {
+            Ok(Decoded::Text(content))
+        }
+    }
+
+    fn format_page(&self, content: &str) -> Result<String, ToolError> {
+        let lines: Vec<&str> = content.lines().collect();
+        let total_lines = lines.len();
+
+        if total_lines == 0 {
+            return Ok("File is empty (0 lines)\n".to_string());
+        }
+
+        let first_line = self.offset.max(1) as usize;
+        if first_line > total_lines {
+            return Err(ToolError::InvalidArgument(format!(
+                "Offset {} is past the end of the file ({} lines)",
+                first_line, total_lines
+            )));
+        }
+
+        let line_limit = self.limit.max(1) as usize;
+        let max_bytes = self.max_bytes as usize;
+
+        let mut body = String::new();
+        let mut last_line = first_line - 1;
+
+        for (index, line) in lines.iter().enumerate().skip(first_line - 1) {
+            let line_number = index + 1;
+            if line_number - first_line >= line_limit {
+                break;
+            }
+
+            let numbered = format!(
+                "{:>width$}\t{}\n",
+                line_number,
+                line,
+                width = LINE_NUMBER_WIDTH
+            );
+            if body.len() + numbered.len() > max_bytes {
+                if body.is_empty() {
+                    body.push_str(truncate_to_char_boundary(&numbered, max_bytes));
+                    body.push_str(" [line truncated]\n");
+                    last_line = line_number;
+                }
+                break;
+            }
+
+            body.push_str(&numbered);
+            last_line = line_number;
+        }
+
+        Ok(self.add_pagination_info(body, first_line, last_line, total_lines))
+    }
+
+    fn add_pagination_info(
+        &self,
+        body: String,
+        first_line: usize,
+        last_line: usize,
+        total_lines: usize,
+    ) -> String {
+        let has_more_lines = last_line < total_lines;
+
+        if has_more_lines {
+            format!(
+                "Lines {}-{} of {} total:\n{}\n... truncated, continue at line {} (use offset: {})\n",
+                first_line,
+                last_line,
+                total_lines,
+                body,
+                last_line + 1,
+                last_line + 1
+            )
+        } else {
+            format!(
+                "Lines {}-{} of {} total:\n{}",
+                first_line, last_line, total_lines, body
+            )
+        }
+    }
+}
+
+struct PreparedImage {
+    bytes: Vec<u8>,
+    mime: &'static str,
+    width: u32,
+    height: u32,
+    original_size: Option<(u32, u32)>,
+}
+
+/// Downscales an image when it exceeds `max_dimension`, or otherwise when it
+/// is too large to embed, to a size its pixel count suggests will fit.
+fn prepare_image(
+    bytes: Vec<u8>,
+    mime: &'static str,
+    max_dimension: Option<u32>,
+) -> Result<PreparedImage, ToolError> {
+    let invalid =
+        |e: image::ImageError| ToolError::InvalidArgument(format!("Invalid image: {}", e));
+
+    let (width, height) = ImageReader::new(Cursor::new(&bytes))
+        .with_guessed_format()?
+        .into_dimensions()
+        .map_err(invalid)?;
+
+    let target = match max_dimension {
+        Some(max) if width > max || height > max => max,
+        _ if bytes.len() > MAX_EMBEDDED_BYTES => {
+            let scale = (MAX_EMBEDDED_BYTES as f64 / bytes.len() as f64).sqrt() * 0.9;
+            let fitting = (width.max(height) as f64 * scale) as u32;
+            DEFAULT_DOWNSCALE_DIMENSION.min(fitting).max(1)
+        }
+        _ => {
+            return Ok(PreparedImage {
+                bytes,
+                mime,
+                width,
+                height,
+                original_size: None,
+            })
+        }
+    };
+
+    let decoded = image::load_from_memory(&bytes).map_err(invalid)?;
+    let resized = decoded.resize(target, target, FilterType::Triangle);
+
+    // JPEG cannot carry alpha; everything else is re-encoded as PNG.
+    let (resized, format, mime) = if mime == "image/jpeg" {
+        let rgb = image::DynamicImage::ImageRgb8(resized.to_rgb8());
+        (rgb, ImageFormat::Jpeg, "image/jpeg")
+    } else {
+        (resized, ImageFormat::Png, "image/png")
+    };
+
+    let mut encoded = Cursor::new(Vec::new());
+    resized.write_to(&mut encoded, format).map_err(invalid)?;
+
+    Ok(PreparedImage {
+        bytes: encoded.into_inner(),
+        mime,
+        width: resized.width(),
+        height: resized.height(),
+        original_size: Some((width, height)),
+    })
+}
+
+fn pdf_result(path: &Path, bytes: &[u8]) -> Result<CallToolResult, ToolError> {
+    if bytes.len() > MAX_EMBEDDED_BYTES {
+        return Err(ToolError::InvalidArgument(format!(
+            "PDF is {} bytes, which exceeds the {} byte limit",
+            bytes.len(),
+            MAX_EMBEDDED_BYTES
+        )));
+    }
+
+    let summary = format!("PDF: {} ({} bytes)", path.display(), bytes.len());
+    Ok(CallToolResult::success(vec![
+        Content::text(summary),
+        Content::resource(ResourceContents::BlobResourceContents {
+            uri: format!("file://{}", path.display()),
+            mime_type: Some("application/pdf".to_string()),
+            blob: BASE64.encode(bytes),
+            meta: None,
+        }),
+    ]))
+}
+
+enum Decoded {
+    Text(String),
+    Lossy(String),
+    Binary,
+}
+
+fn format_binary_summary(path: &Path, bytes: &[u8]) -> String {
+    let mut summary = format!(
+        "Binary file: {}\nSize: {} bytes\nMIME type: {}\n",
+        path.display(),
+        bytes.len(),
+        FileType::guess_mime(path, bytes)
+    );
+
+    if bytes.is_empty() {
+        return summary;
+    }
+
+    let shown = &bytes[..bytes.len().min(HEX_DUMP_BYTES)];
+    summary.push_str(&format!("First {} bytes:\n", shown.len()));
+    summary.push_str(&hex_dump(shown));
+    summary
+}
+
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (row, chunk) in bytes.chunks(HEX_DUMP_ROW_WIDTH).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        dump.push_str(&format!(
+            "{:08x}  {:<width$}  |{}|\n",
+            row * HEX_DUMP_ROW_WIDTH,
+            hex.join(" "),
+            ascii,
+            width = HEX_DUMP_ROW_WIDTH * 3 - 1
+        ));
+    }
+
+    dump
+}
+
+fn truncate_to_char_boundary(text: &str, max_bytes: usize) -> &str {
+    let mut end = max_bytes.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
     }
+    &text[..end]
 }
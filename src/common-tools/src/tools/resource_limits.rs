@@ -0,0 +1,130 @@
+use serde::Deserialize;
+use tokio::process::Command;
+
+use super::ToolError;
+
+/// Limits applied to a spawned command and everything it starts.
+///
+/// CPU time, memory, process count and file size are enforced by the kernel
+/// through `setrlimit` in the child before it execs, and are only supported
+/// on Linux. Wall time is enforced by the server on every platform, including
+/// for background processes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, schemars::JsonSchema)]
+pub struct ResourceLimits {
+    #[schemars(description = "CPU seconds the command may use before it is sent SIGXCPU")]
+    pub cpu_time_secs: Option<u64>,
+    #[schemars(
+        description = "Milliseconds the command may run in total, in the foreground or background"
+    )]
+    pub wall_time_ms: Option<u64>,
+    #[schemars(description = "Maximum virtual memory in bytes (RLIMIT_AS)")]
+    pub memory_bytes: Option<u64>,
+    #[schemars(
+        description = "Maximum processes for the user running the server, counting existing ones (RLIMIT_NPROC)"
+    )]
+    pub max_processes: Option<u64>,
+    #[schemars(
+        description = "Largest file in bytes the command may write before it is sent SIGXFSZ"
+    )]
+    pub max_file_size_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Combines two sets of limits, keeping the stricter value of each.
+    pub fn tightest(&self, other: &ResourceLimits) -> ResourceLimits {
+        fn min(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        ResourceLimits {
+            cpu_time_secs: min(self.cpu_time_secs, other.cpu_time_secs),
+            wall_time_ms: min(self.wall_time_ms, other.wall_time_ms),
+            memory_bytes: min(self.memory_bytes, other.memory_bytes),
+            max_processes: min(self.max_processes, other.max_processes),
+            max_file_size_bytes: min(self.max_file_size_bytes, other.max_file_size_bytes),
+        }
+    }
+
+    /// How long a call with `timeout_ms` may actually run: the timeout, or
+    /// the wall time limit if that is shorter.
+    pub fn wall_timeout_ms(&self, timeout_ms: Option<u64>) -> Option<u64> {
+        match (timeout_ms, self.wall_time_ms) {
+            (Some(timeout), Some(limit)) => Some(timeout.min(limit)),
+            (timeout, limit) => timeout.or(limit),
+        }
+    }
+
+    fn has_kernel_limits(&self) -> bool {
+        self.cpu_time_secs.is_some()
+            || self.memory_bytes.is_some()
+            || self.max_processes.is_some()
+            || self.max_file_size_bytes.is_some()
+    }
+
+    /// Arranges for the kernel-enforced limits to be set in the child.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn apply(&self, cmd: &mut Command) -> Result<(), ToolError> {
+        if !self.has_kernel_limits() {
+            return Ok(());
+        }
+
+        // The hard CPU limit is a second above the soft one, so the command
+        // gets SIGXCPU and a chance to exit before the kernel sends SIGKILL.
+        let limits = [
+            (
+                libc::RLIMIT_CPU,
+                self.cpu_time_secs.map(|s| (s, s.saturating_add(1))),
+            ),
+            (libc::RLIMIT_AS, self.memory_bytes.map(|b| (b, b))),
+            (libc::RLIMIT_NPROC, self.max_processes.map(|n| (n, n))),
+            (libc::RLIMIT_FSIZE, self.max_file_size_bytes.map(|b| (b, b))),
+        ];
+
+        // SAFETY: only getrlimit and setrlimit, which are async-signal-safe,
+        // run between fork and exec.
+        unsafe {
+            cmd.pre_exec(move || {
+                for (resource, limit) in limits {
+                    let Some((soft, hard)) = limit else {
+                        continue;
+                    };
+
+                    let mut current = libc::rlimit {
+                        rlim_cur: 0,
+                        rlim_max: 0,
+                    };
+                    if libc::getrlimit(resource, &mut current) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+
+                    // Unprivileged processes can only lower their hard limits.
+                    let hard = (hard as libc::rlim_t).min(current.rlim_max);
+                    let requested = libc::rlimit {
+                        rlim_cur: (soft as libc::rlim_t).min(hard),
+                        rlim_max: hard,
+                    };
+                    if libc::setrlimit(resource, &requested) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn apply(&self, _cmd: &mut Command) -> Result<(), ToolError> {
+        if self.has_kernel_limits() {
+            return Err(ToolError::InvalidArgument(
+                "CPU time, memory, process and file size limits are only supported on Linux"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -1,69 +1,458 @@
+use regex::{Captures, Regex, RegexBuilder};
 use rmcp::model::{CallToolResult, Content};
 use serde::{Deserialize, Serialize};
-use tokio::fs;
+use std::path::PathBuf;
 
-use super::{resolve_path, ToolError};
+use super::atomic_write::write_files;
+use super::diff::{diff_name, render_diffs, FileDiff, MAX_DIFF_PREVIEW_BYTES};
+use super::edit_diagnostics::{
+    ambiguous_message, match_locations, normalize_whitespace, not_found_message,
+};
+use super::text_document::TextDocument;
+use super::{ToolError, Workspace};
 
-#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+#[derive(Debug, Default, Deserialize, Serialize, schemars::JsonSchema)]
 pub struct EditOperation {
     /// The text to search for. Can include multiple lines and doesn't need exact whitespace matching.
     pub search: String,
-    /// The text to replace it with. Use empty string to delete.
+    /// The text to replace it with. Use empty string to delete. In regex mode, $1 and $name
+    /// take the longest run of letters, digits and underscores as the group, so write ${1}bar
+    /// to follow a group with such text.
     pub replace: String,
+    /// Replace every match instead of one (default: false).
+    #[serde(default)]
+    pub replace_all: bool,
+    /// Replace only this match, counting from 1 in file order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occurrence: Option<usize>,
+    /// Replace the match that starts closest to this line number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_line: Option<usize>,
+    /// How search is interpreted: "text" (default) or "regex". In regex mode, replace may refer
+    /// to capture groups as $1, $name, ${1} or ${name}, and $$ is a literal $. Referring to a
+    /// group the regex doesn't have is an error.
+    #[serde(default)]
+    pub mode: EditMode,
+    /// Regex mode: ^ and $ match at the start and end of every line (default: false).
+    #[serde(default)]
+    pub multi_line: bool,
+    /// Regex mode: ignore case when matching (default: false).
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Fail unless the edit makes exactly this many replacements.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_count: Option<usize>,
 }
 
-pub struct SearchReplaceEditTool {
+/// How an edit's search text is matched.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EditMode {
+    /// Literal text, falling back to matching whole lines with whitespace ignored.
+    #[default]
+    Text,
+    /// A regular expression.
+    Regex,
+}
+
+/// Edits to make to one file.
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct FileEdits {
+    /// The path to the file to edit.
     pub path: String,
+    /// Search and replace operations, applied in order.
     pub edits: Vec<EditOperation>,
 }
 
+impl FileEdits {
+    /// Builds the list of files to edit from the single-file `path` and
+    /// `edits` parameters and the multi-file `files` parameter.
+    pub fn collect(
+        path: Option<String>,
+        edits: Option<Vec<EditOperation>>,
+        files: Option<Vec<FileEdits>>,
+    ) -> Result<Vec<FileEdits>, ToolError> {
+        let mut all = Vec::new();
+        match (path, edits) {
+            (Some(path), Some(edits)) => all.push(FileEdits { path, edits }),
+            (None, None) => {}
+            _ => {
+                return Err(ToolError::InvalidArgument(
+                    "path and edits must be given together".to_string(),
+                ))
+            }
+        }
+        all.extend(files.unwrap_or_default());
+
+        if all.is_empty() {
+            return Err(ToolError::InvalidArgument(
+                "Pass path and edits, or files".to_string(),
+            ));
+        }
+        Ok(all)
+    }
+}
+
+pub struct SearchReplaceEditTool {
+    /// Every file must match all of its edits before any file is changed.
+    pub files: Vec<FileEdits>,
+    /// Report the diff the edits would produce without writing anything.
+    pub dry_run: bool,
+    pub workspace: Workspace,
+}
+
+/// A file being edited, with the edits applied so far. Edits work on the
+/// document's normalized text and are rendered back in its own line endings
+/// when written.
+struct EditedFile {
+    path: PathBuf,
+    document: TextDocument,
+    content: String,
+    edit_count: usize,
+}
+
 impl SearchReplaceEditTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
-        let abs_path = resolve_path(&self.path, None);
+        let regex_edits = self
+            .files
+            .iter()
+            .flat_map(|file| &file.edits)
+            .filter(|edit| edit.mode == EditMode::Regex);
+        for edit in regex_edits {
+            // An invalid regex is reported with the file it was meant for.
+            if let Ok(regex) = build_regex(edit) {
+                check_group_references(&regex, &edit.replace)
+                    .map_err(ToolError::InvalidArgument)?;
+            }
+        }
+
+        let mut edited: Vec<EditedFile> = Vec::new();
+        let mut failures = Vec::new();
 
-        if !abs_path.exists() {
-            return Err(ToolError::FileNotFound(abs_path.display().to_string()));
+        for file in &self.files {
+            let abs_path = self.workspace.resolve_write(&file.path)?;
+
+            // The same file may be listed more than once; later groups see
+            // the result of earlier ones.
+            let index = match edited.iter().position(|e| e.path == abs_path) {
+                Some(index) => index,
+                None => {
+                    if !abs_path.exists() {
+                        return Err(ToolError::FileNotFound(abs_path.display().to_string()));
+                    }
+                    let document = TextDocument::read(&abs_path).await?;
+                    edited.push(EditedFile {
+                        path: abs_path,
+                        content: document.text.clone(),
+                        document,
+                        edit_count: 0,
+                    });
+                    edited.len() - 1
+                }
+            };
+
+            let target = &mut edited[index];
+            match apply_all_edits(&target.content, &file.edits) {
+                Ok(content) => {
+                    target.content = content;
+                    target.edit_count += file.edits.len();
+                }
+                Err(e) => failures.push(format!("{}: {}", target.path.display(), e)),
+            }
+        }
+
+        if !failures.is_empty() {
+            let message = if edited.len() > 1 {
+                format!("{}\nNo files were changed.", failures.join("\n"))
+            } else {
+                failures.join("\n")
+            };
+            return Err(ToolError::Other(message));
         }
 
-        let original_content = fs::read_to_string(&abs_path).await.map_err(ToolError::Io)?;
-        let result_content = apply_all_edits(&original_content, &self.edits)?;
+        let diffs: Vec<FileDiff> = edited
+            .iter()
+            .map(|file| {
+                FileDiff::new(
+                    &diff_name(&file.path, &self.workspace),
+                    &file.document.text,
+                    &file.content,
+                )
+            })
+            .collect();
+
+        let summary = match edited.as_slice() {
+            [file] => format!(
+                "{} edits to {} ({})",
+                file.edit_count,
+                file.path.display(),
+                diffs[0].stat()
+            ),
+            files => {
+                let total: usize = files.iter().map(|f| f.edit_count).sum();
+                let lines: Vec<String> = files
+                    .iter()
+                    .zip(&diffs)
+                    .map(|(f, d)| {
+                        format!(
+                            "  {} ({} edits, {})",
+                            f.path.display(),
+                            f.edit_count,
+                            d.stat()
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{} edits to {} files:\n{}",
+                    total,
+                    files.len(),
+                    lines.join("\n")
+                )
+            }
+        };
+
+        if self.dry_run {
+            let message = format!(
+                "Dry run, nothing was written. Applying {}\n\n{}",
+                summary,
+                render_diffs(&diffs, None)
+            );
+            return Ok(CallToolResult::success(vec![Content::text(message)]));
+        }
 
-        fs::write(&abs_path, &result_content)
+        let writes: Vec<(PathBuf, String)> = edited
+            .iter()
+            .map(|file| (file.path.clone(), file.document.render(&file.content)))
+            .collect();
+        tokio::task::spawn_blocking(move || write_files(&writes))
             .await
-            .map_err(ToolError::Io)?;
+            .map_err(|e| ToolError::Other(e.to_string()))??;
 
         let message = format!(
-            "Successfully applied {} edits to {}",
-            self.edits.len(),
-            abs_path.display()
+            "Successfully applied {}\n\n{}",
+            summary,
+            render_diffs(&diffs, Some(MAX_DIFF_PREVIEW_BYTES))
         );
         Ok(CallToolResult::success(vec![Content::text(message)]))
     }
 }
 
-fn apply_all_edits(content: &str, edits: &[EditOperation]) -> Result<String, ToolError> {
+fn apply_all_edits(content: &str, edits: &[EditOperation]) -> Result<String, String> {
     let mut result_content = content.to_string();
 
     for (i, edit) in edits.iter().enumerate() {
         result_content = apply_edit(&result_content, edit)
-            .map_err(|e| ToolError::Other(format!("Edit {} failed: {}", i + 1, e)))?;
+            .map_err(|e| format!("Edit {} failed: {}", i + 1, e))?;
     }
 
     Ok(result_content)
 }
 
+/// Which of several matches an edit applies to.
+enum Selection {
+    /// The first exact match, or the only fuzzy match.
+    Default,
+    All,
+    /// 1-based, in file order.
+    Occurrence(usize),
+    /// The match starting closest to this 1-based line.
+    NearestTo(usize),
+}
+
+impl Selection {
+    fn from_edit(edit: &EditOperation) -> Result<Self, String> {
+        match (edit.replace_all, edit.occurrence, edit.anchor_line) {
+            (false, None, None) => Ok(Selection::Default),
+            (true, None, None) => Ok(Selection::All),
+            (false, Some(0), None) => Err("occurrence counts from 1".to_string()),
+            (false, Some(n), None) => Ok(Selection::Occurrence(n)),
+            (false, None, Some(line)) => Ok(Selection::NearestTo(line)),
+            _ => Err("Use only one of replace_all, occurrence and anchor_line".to_string()),
+        }
+    }
+
+    /// Picks matches from `ranges`, the 0-based line ranges of every match
+    /// in file order, returning their indices.
+    fn choose(&self, ranges: &[(usize, usize)], fuzzy: bool) -> Result<Vec<usize>, String> {
+        match *self {
+            Selection::Default if fuzzy && ranges.len() > 1 => Err(ambiguous_message(ranges)),
+            Selection::Default => Ok(vec![0]),
+            Selection::All => Ok((0..ranges.len()).collect()),
+            Selection::Occurrence(n) if n > ranges.len() => Err(format!(
+                "Occurrence {} requested but only {} matches were found, at {}",
+                n,
+                ranges.len(),
+                match_locations(ranges)
+            )),
+            Selection::Occurrence(n) => Ok(vec![n - 1]),
+            Selection::NearestTo(line) => {
+                let anchor = line.saturating_sub(1);
+                let nearest = (0..ranges.len())
+                    .min_by_key(|&i| ranges[i].0.abs_diff(anchor))
+                    .unwrap_or_default();
+                Ok(vec![nearest])
+            }
+        }
+    }
+}
+
 fn apply_edit(content: &str, edit: &EditOperation) -> Result<String, String> {
-    if content.contains(&edit.search) {
-        let new_content = content.replacen(&edit.search, &edit.replace, 1);
-        return Ok(new_content);
+    if edit.search.is_empty() {
+        return Err("Search text is empty".to_string());
+    }
+    let selection = Selection::from_edit(edit)?;
+
+    let (new_content, replacements) = match edit.mode {
+        EditMode::Text => {
+            if edit.multi_line || edit.case_insensitive {
+                return Err(
+                    "multi_line and case_insensitive only apply with mode \"regex\"".to_string(),
+                );
+            }
+            text_search_replace(content, edit, &selection)?
+        }
+        EditMode::Regex => regex_search_replace(content, edit, &selection)?,
+    };
+
+    match edit.expected_count {
+        Some(expected) if expected != replacements => Err(format!(
+            "Expected {} replacements but the edit would make {}",
+            expected, replacements
+        )),
+        _ => Ok(new_content),
+    }
+}
+
+fn text_search_replace(
+    content: &str,
+    edit: &EditOperation,
+    selection: &Selection,
+) -> Result<(String, usize), String> {
+    let exact: Vec<usize> = content
+        .match_indices(&edit.search)
+        .map(|(start, _)| start)
+        .collect();
+    if exact.is_empty() {
+        return fuzzy_search_replace(content, &edit.search, &edit.replace, selection);
+    }
+
+    let ranges: Vec<(usize, usize)> = exact
+        .iter()
+        .map(|&start| line_range(content, start..start + edit.search.len()))
+        .collect();
+
+    let chosen = selection.choose(&ranges, false)?;
+    let mut new_content = content.to_string();
+    for &i in chosen.iter().rev() {
+        let start = exact[i];
+        new_content.replace_range(start..start + edit.search.len(), &edit.replace);
+    }
+    Ok((new_content, chosen.len()))
+}
+
+/// Replaces matches of the regular expression `edit.search`, expanding
+/// `$1` and `${name}` in `edit.replace` from each match's capture groups.
+fn regex_search_replace(
+    content: &str,
+    edit: &EditOperation,
+    selection: &Selection,
+) -> Result<(String, usize), String> {
+    let regex = build_regex(edit)?;
+    let captures: Vec<Captures> = regex.captures_iter(content).collect();
+    if captures.is_empty() {
+        return Err("Regex matched nothing in the file".to_string());
+    }
+
+    let ranges: Vec<(usize, usize)> = captures
+        .iter()
+        .map(|caps| line_range(content, caps.get(0).map_or(0..0, |m| m.range())))
+        .collect();
+
+    let chosen = selection.choose(&ranges, false)?;
+    let mut new_content = content.to_string();
+    for &i in chosen.iter().rev() {
+        let caps = &captures[i];
+        let mut replacement = String::new();
+        caps.expand(&edit.replace, &mut replacement);
+        if let Some(whole) = caps.get(0) {
+            new_content.replace_range(whole.range(), &replacement);
+        }
     }
+    Ok((new_content, chosen.len()))
+}
+
+fn build_regex(edit: &EditOperation) -> Result<Regex, String> {
+    RegexBuilder::new(&edit.search)
+        .multi_line(edit.multi_line)
+        .case_insensitive(edit.case_insensitive)
+        .build()
+        .map_err(|e| format!("Invalid regex: {}", e))
+}
+
+/// Rejects a replacement that refers to a group `regex` doesn't have, which
+/// would otherwise quietly expand to nothing. Names are read the way
+/// `Captures::expand` reads them, so `$1bar` refers to a group `1bar`.
+fn check_group_references(regex: &Regex, replace: &str) -> Result<(), String> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut rest = replace;
+
+    while let Some(dollar) = rest.find('$') {
+        rest = &rest[dollar + 1..];
+        let (name, written) = if let Some(escaped) = rest.strip_prefix('$') {
+            rest = escaped;
+            continue;
+        } else if let Some(braced) = rest.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                continue;
+            };
+            (&braced[..end], format!("${{{}}}", &braced[..end]))
+        } else {
+            let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            (&rest[..end], format!("${}", &rest[..end]))
+        };
+        if name.is_empty() {
+            continue;
+        }
 
-    fuzzy_search_replace(content, &edit.search, &edit.replace)
+        let exists = match name.parse::<usize>() {
+            Ok(index) => index < regex.captures_len(),
+            Err(_) => regex.capture_names().flatten().any(|n| n == name),
+        };
+        if !exists {
+            return Err(format!(
+                "replace refers to group {}, which the regex doesn't have. Names run as far as \
+                 letters, digits and underscores go; write ${{1}} or ${{name}} to put such text \
+                 right after a group",
+                written
+            ));
+        }
+    }
+
+    Ok(())
 }
 
-fn fuzzy_search_replace(content: &str, search: &str, replace: &str) -> Result<String, String> {
-    let search_normalized = normalize_whitespace(search);
-    let search_lines: Vec<&str> = search_normalized.split('\n').collect();
+/// The 0-based, end-exclusive range of lines that the bytes in `span` cover.
+fn line_range(content: &str, span: std::ops::Range<usize>) -> (usize, usize) {
+    let first = content[..span.start].matches('\n').count();
+    let last = first + content[span].matches('\n').count();
+    (first, last + 1)
+}
+
+/// Finds the search text line by line, ignoring differences in whitespace
+/// within each line but not where the lines break.
+fn fuzzy_search_replace(
+    content: &str,
+    search: &str,
+    replace: &str,
+    selection: &Selection,
+) -> Result<(String, usize), String> {
+    let search_lines: Vec<&str> = search.split('\n').collect();
+    let search_normalized: Vec<String> = search_lines
+        .iter()
+        .map(|line| normalize_whitespace(line))
+        .collect();
     let content_lines: Vec<&str> = content.split('\n').collect();
 
     let mut matches = Vec::new();
@@ -72,7 +461,7 @@ fn fuzzy_search_replace(content: &str, search: &str, replace: &str) -> Result<St
         let mut match_candidate = true;
         let match_end_idx = start_idx + search_lines.len();
 
-        for (i, search_line) in search_lines.iter().enumerate() {
+        for (i, search_line) in search_normalized.iter().enumerate() {
             if start_idx + i >= content_lines.len() {
                 match_candidate = false;
                 break;
@@ -93,47 +482,67 @@ fn fuzzy_search_replace(content: &str, search: &str, replace: &str) -> Result<St
     }
 
     if matches.is_empty() {
-        return Err(
-            "Search text not found in file. Please verify the text exists and check for typos."
-                .to_string(),
-        );
+        return Err(not_found_message(&content_lines, &search_lines));
     }
 
-    if matches.len() > 1 {
+    // Matches of a repeated pattern may overlap. Each is a different place to
+    // edit, but they can't all be replaced at once.
+    let chosen = selection.choose(&matches, true)?;
+    if chosen
+        .windows(2)
+        .any(|pair| matches[pair[1]].0 < matches[pair[0]].1)
+    {
         return Err(format!(
-            "Ambiguous: found {} potential matches",
-            matches.len()
+            "Matches at {} overlap, so they can't all be replaced. Pick one with occurrence \
+             or anchor_line.",
+            match_locations(&matches)
         ));
     }
 
-    let (start_idx, end_idx) = matches[0];
-    let first_content_line = content_lines[start_idx];
+    let mut new_content_lines: Vec<String> = content_lines.iter().map(|s| s.to_string()).collect();
+    for &i in chosen.iter().rev() {
+        let (start_idx, end_idx) = matches[i];
+        let replacement = reindent_replacement(content_lines[start_idx], &search_lines, replace);
+        new_content_lines.splice(start_idx..end_idx, replacement);
+    }
+
+    Ok((new_content_lines.join("\n"), chosen.len()))
+}
+
+/// Indents `replace` to fit where the search text was found, starting at
+/// `first_content_line`. Replacement lines are taken to be indented relative
+/// to the search text's first line, so nesting within them is kept.
+fn reindent_replacement(
+    first_content_line: &str,
+    search_lines: &[&str],
+    replace: &str,
+) -> Vec<String> {
     let indentation = extract_indentation(first_content_line);
+    let search_indentation = extract_indentation(search_lines[0]);
+    let reindent = |line: &str| {
+        format!(
+            "{}{}",
+            indentation,
+            line.strip_prefix(search_indentation.as_str())
+                .unwrap_or(line)
+        )
+    };
 
     let replace_lines: Vec<&str> = replace.split('\n').collect();
     let mut indented_replace_lines = Vec::new();
 
     if !replace_lines.is_empty() {
-        indented_replace_lines.push(format!("{}{}", indentation, replace_lines[0]));
+        indented_replace_lines.push(reindent(replace_lines[0]));
         for line in replace_lines.iter().skip(1) {
             if line.trim().is_empty() {
                 indented_replace_lines.push(line.to_string());
             } else {
-                indented_replace_lines.push(format!("{}{}", indentation, line));
+                indented_replace_lines.push(reindent(line));
             }
         }
     }
 
-    let mut new_content_lines = Vec::new();
-    new_content_lines.extend_from_slice(&content_lines[..start_idx]);
-    new_content_lines.extend(indented_replace_lines.iter().map(|s| s.as_str()));
-    new_content_lines.extend_from_slice(&content_lines[end_idx..]);
-
-    Ok(new_content_lines.join("\n"))
-}
-
-fn normalize_whitespace(text: &str) -> String {
-    text.split_whitespace().collect::<Vec<_>>().join(" ")
+    indented_replace_lines
 }
 
 fn extract_indentation(line: &str) -> String {
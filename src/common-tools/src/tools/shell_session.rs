@@ -0,0 +1,433 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio_util::sync::CancellationToken;
+
+use super::execute_command::Shell;
+use super::process::{kill_process_group, signal_process_group};
+use super::resource_limits::ResourceLimits;
+use super::ToolError;
+
+/// How long to keep reading the other stream once the shell has closed one.
+const EXIT_DRAIN_GRACE: Duration = Duration::from_secs(2);
+
+/// A long-lived shell that commands are fed to one at a time, so `cd`,
+/// `export` and `source` carry over from one command to the next.
+pub(crate) struct ShellSession {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    /// Output that arrived after the previous command's end marker, from
+    /// jobs the shell left running in the background.
+    stdout_pending: Vec<u8>,
+    stderr_pending: Vec<u8>,
+    working_directory: PathBuf,
+    /// Set once the shell has exited or been killed and reaped.
+    closed: bool,
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        if !self.closed {
+            if let Some(pid) = self.child.id() {
+                signal_process_group(pid);
+            }
+        }
+    }
+}
+
+/// How a command sent to a shell session ended.
+pub(crate) enum SessionRunEnd {
+    Finished {
+        exit_code: i32,
+        working_directory: PathBuf,
+    },
+    /// The shell itself exited, for example because the command ran `exit`.
+    ShellExited(Option<ExitStatus>),
+    TimedOut,
+    Cancelled,
+}
+
+pub(crate) struct SessionRun {
+    pub(crate) stdout: String,
+    pub(crate) stderr: String,
+    pub(crate) end: SessionRunEnd,
+}
+
+/// Collects one stream of a command's output up to the end marker the
+/// wrapper script prints after it.
+#[derive(Default)]
+struct MarkedStream {
+    buffer: Vec<u8>,
+    /// Where the marker starts, once its whole line has arrived.
+    end: Option<usize>,
+    /// The range of the marker's line after the marker itself.
+    trailer: Option<(usize, usize)>,
+    eof: bool,
+}
+
+impl MarkedStream {
+    fn done(&self) -> bool {
+        self.end.is_some() || self.eof
+    }
+
+    /// Looks for `marker` followed by the rest of its line.
+    fn scan(&mut self, marker: &[u8]) {
+        let Some(start) = find(&self.buffer, marker) else {
+            return;
+        };
+        let after = start + marker.len();
+        if let Some(newline) = find(&self.buffer[after..], b"\n") {
+            self.end = Some(start);
+            self.trailer = Some((after, after + newline));
+        }
+    }
+
+    fn output(&self) -> String {
+        let end = self.end.unwrap_or(self.buffer.len());
+        String::from_utf8_lossy(&self.buffer[..end]).into_owned()
+    }
+
+    /// The text between the marker and the end of its line.
+    fn trailer(&self) -> String {
+        self.trailer
+            .map(|(start, end)| String::from_utf8_lossy(&self.buffer[start..end]).into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Bytes that arrived after the marker's line.
+    fn leftover(&mut self) -> Vec<u8> {
+        match self.trailer {
+            Some((_, end)) => self.buffer.split_off(end + 1),
+            None => Vec::new(),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Quotes `text` as a single word for any POSIX shell.
+fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// A string that won't turn up in a command's own output by accident.
+fn new_marker() -> String {
+    let nonce = RandomState::new().hash_one(std::process::id());
+    format!("__COMMON_TOOLS_END_{:016x}__", nonce)
+}
+
+/// Wraps `command` so the shell prints `marker`, the exit status and the
+/// working directory on stdout, and `marker` on stderr, once it finishes.
+/// The command is evaluated from a variable so nothing it contains can be
+/// mistaken for the wrapper, and reads from /dev/null instead of consuming
+/// the commands that follow it.
+fn wrap_command(command: &str, marker: &str) -> String {
+    format!(
+        "__ct_command={}\n\
+         eval \"$__ct_command\" < /dev/null\n\
+         __ct_status=$?\n\
+         unset __ct_command\n\
+         printf '%s %s %s\\n' '{marker}' \"$__ct_status\" \"$PWD\"\n\
+         printf '%s\\n' '{marker}' >&2\n",
+        single_quote(command),
+    )
+}
+
+impl ShellSession {
+    fn spawn(
+        shell: Shell,
+        working_directory: &Path,
+        env: &HashMap<String, String>,
+        limits: &ResourceLimits,
+    ) -> Result<Self, ToolError> {
+        let (program, flags): (&str, &[&str]) = match shell {
+            Shell::Sh => ("sh", &[]),
+            Shell::Bash => ("bash", &["--noprofile", "--norc"]),
+            Shell::Zsh => ("zsh", &["-f"]),
+            Shell::Pwsh | Shell::Cmd => {
+                return Err(ToolError::InvalidArgument(
+                    "Shell sessions support sh, bash and zsh".to_string(),
+                ))
+            }
+        };
+
+        let mut cmd = Command::new(program);
+        cmd.args(flags)
+            .current_dir(working_directory)
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        limits.apply(&mut cmd)?;
+
+        // The shell leads its own process group so closing the session also
+        // stops everything it started.
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| ToolError::CommandFailed(format!("Failed to start {}: {}", program, e)))?;
+
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(ToolError::CommandFailed(format!(
+                "Failed to connect to {}",
+                program
+            )));
+        };
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            stderr,
+            stdout_pending: Vec::new(),
+            stderr_pending: Vec::new(),
+            working_directory: working_directory.to_path_buf(),
+            closed: false,
+        })
+    }
+
+    async fn run(
+        &mut self,
+        command: &str,
+        timeout: Option<Duration>,
+        cancellation_token: &CancellationToken,
+    ) -> Result<SessionRun, ToolError> {
+        let marker = new_marker();
+        let mut stdout = MarkedStream {
+            buffer: std::mem::take(&mut self.stdout_pending),
+            ..Default::default()
+        };
+        let mut stderr = MarkedStream {
+            buffer: std::mem::take(&mut self.stderr_pending),
+            ..Default::default()
+        };
+
+        let script = wrap_command(command, &marker);
+        let written = async {
+            self.stdin.write_all(script.as_bytes()).await?;
+            self.stdin.flush().await
+        }
+        .await;
+        // A shell that already exited shows up as end of file below.
+        if written.is_err() {
+            stdout.eof = true;
+            stderr.eof = true;
+        }
+
+        let deadline = tokio::time::sleep(timeout.unwrap_or(Duration::MAX));
+        tokio::pin!(deadline);
+        let mut draining = false;
+        let mut stdout_chunk = [0u8; 8192];
+        let mut stderr_chunk = [0u8; 8192];
+
+        let interrupted = loop {
+            if stdout.done() && stderr.done() {
+                break None;
+            }
+            if (stdout.eof || stderr.eof) && !draining {
+                draining = true;
+                deadline
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + EXIT_DRAIN_GRACE);
+            }
+
+            tokio::select! {
+                n = read_chunk(&mut self.stdout, &mut stdout_chunk), if !stdout.done() => {
+                    receive(&mut stdout, &stdout_chunk[..n], marker.as_bytes());
+                }
+                n = read_chunk(&mut self.stderr, &mut stderr_chunk), if !stderr.done() => {
+                    receive(&mut stderr, &stderr_chunk[..n], marker.as_bytes());
+                }
+                _ = &mut deadline => {
+                    break (!draining).then_some(SessionRunEnd::TimedOut);
+                }
+                _ = cancellation_token.cancelled() => break Some(SessionRunEnd::Cancelled),
+            }
+        };
+
+        let end = match interrupted {
+            Some(end) => {
+                self.close().await;
+                end
+            }
+            None if stdout.end.is_some() && stderr.end.is_some() => {
+                let trailer = stdout.trailer();
+                let (code, dir) = trailer
+                    .trim_start()
+                    .split_once(' ')
+                    .unwrap_or((&trailer, ""));
+                if !dir.is_empty() {
+                    self.working_directory = PathBuf::from(dir);
+                }
+                self.stdout_pending = stdout.leftover();
+                self.stderr_pending = stderr.leftover();
+                SessionRunEnd::Finished {
+                    exit_code: code.parse().unwrap_or(-1),
+                    working_directory: self.working_directory.clone(),
+                }
+            }
+            None => SessionRunEnd::ShellExited(self.close().await),
+        };
+
+        Ok(SessionRun {
+            stdout: stdout.output(),
+            stderr: stderr.output(),
+            end,
+        })
+    }
+
+    /// Kills the shell and everything it started, and reaps it.
+    async fn close(&mut self) -> Option<ExitStatus> {
+        let status = kill_process_group(&mut self.child).await;
+        self.closed = true;
+        status
+    }
+}
+
+async fn read_chunk<R: AsyncRead + Unpin>(pipe: &mut R, chunk: &mut [u8]) -> usize {
+    pipe.read(chunk).await.unwrap_or(0)
+}
+
+fn receive(stream: &mut MarkedStream, chunk: &[u8], marker: &[u8]) {
+    if chunk.is_empty() {
+        stream.eof = true;
+    } else {
+        stream.buffer.extend_from_slice(chunk);
+        stream.scan(marker);
+    }
+}
+
+struct SessionHandle {
+    pid: Option<u32>,
+    session: Arc<tokio::sync::Mutex<ShellSession>>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u32,
+    sessions: BTreeMap<u32, SessionHandle>,
+}
+
+/// Shell sessions started by one MCP session.
+///
+/// Clones share the same sessions. Once the last clone is dropped, every
+/// shell still running is killed along with whatever it started.
+#[derive(Clone, Default)]
+pub struct ShellSessions {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl ShellSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a shell in `working_directory` and returns its session id.
+    pub(crate) fn create(
+        &self,
+        shell: Shell,
+        working_directory: &Path,
+        env: &HashMap<String, String>,
+        limits: &ResourceLimits,
+    ) -> Result<u32, ToolError> {
+        let session = ShellSession::spawn(shell, working_directory, env, limits)?;
+        let pid = session.child.id();
+
+        let mut registry = self.inner.lock().unwrap();
+        registry.next_id += 1;
+        let id = registry.next_id;
+        registry.sessions.insert(
+            id,
+            SessionHandle {
+                pid,
+                session: Arc::new(tokio::sync::Mutex::new(session)),
+            },
+        );
+        Ok(id)
+    }
+
+    fn session(&self, id: u32) -> Result<Arc<tokio::sync::Mutex<ShellSession>>, ToolError> {
+        let registry = self.inner.lock().unwrap();
+        registry
+            .sessions
+            .get(&id)
+            .map(|handle| Arc::clone(&handle.session))
+            .ok_or_else(|| unknown_session(id))
+    }
+
+    /// The directory the session's next command will start in. Waits for a
+    /// command that is already running in the session.
+    pub(crate) async fn working_directory(&self, id: u32) -> Result<PathBuf, ToolError> {
+        let session = self.session(id)?;
+        let session = session.lock().await;
+        Ok(session.working_directory.clone())
+    }
+
+    /// Runs `command` in the session once any command already running in it
+    /// has finished. A session whose command times out or is cancelled is
+    /// killed, since the shell can't be trusted to be at a prompt afterwards.
+    pub(crate) async fn run(
+        &self,
+        id: u32,
+        command: &str,
+        timeout: Option<Duration>,
+        cancellation_token: &CancellationToken,
+    ) -> Result<SessionRun, ToolError> {
+        let session = self.session(id)?;
+        let mut session = session.lock().await;
+        // Closed while this call was waiting for the previous command.
+        if session.closed {
+            return Err(unknown_session(id));
+        }
+
+        let run = session.run(command, timeout, cancellation_token).await?;
+        if session.closed {
+            self.inner.lock().unwrap().sessions.remove(&id);
+        }
+        Ok(run)
+    }
+
+    /// Kills the session's shell and everything it started, including a
+    /// command that is still running, and forgets the session.
+    pub async fn close(&self, id: u32) -> Result<Option<ExitStatus>, ToolError> {
+        let handle = self
+            .inner
+            .lock()
+            .unwrap()
+            .sessions
+            .remove(&id)
+            .ok_or_else(|| unknown_session(id))?;
+
+        // Signal first so a running command releases the session.
+        if let Some(pid) = handle.pid {
+            signal_process_group(pid);
+        }
+
+        let mut session = handle.session.lock().await;
+        if session.closed {
+            return Ok(None);
+        }
+        Ok(session.close().await)
+    }
+}
+
+fn unknown_session(id: u32) -> ToolError {
+    ToolError::InvalidArgument(format!("No shell session with id {}", id))
+}
@@ -0,0 +1,20 @@
+use rmcp::model::{CallToolResult, Content};
+
+use super::shell_session::ShellSessions;
+use super::ToolError;
+
+pub struct ShellSessionCloseTool {
+    pub session_id: u32,
+    pub sessions: ShellSessions,
+}
+
+impl ShellSessionCloseTool {
+    pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
+        self.sessions.close(self.session_id).await?;
+
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Closed shell session {}",
+            self.session_id
+        ))]))
+    }
+}
@@ -0,0 +1,48 @@
+use rmcp::model::{CallToolResult, Content};
+use std::collections::HashMap;
+
+use super::execute_command::Shell;
+use super::resource_limits::ResourceLimits;
+use super::shell_session::ShellSessions;
+use super::{ToolError, Workspace};
+
+pub struct ShellSessionCreateTool {
+    pub shell: Shell,
+    pub working_directory: Option<String>,
+    pub env: HashMap<String, String>,
+    /// Applied to the shell, and so to every command run in the session.
+    pub limits: ResourceLimits,
+    pub workspace: Workspace,
+    pub sessions: ShellSessions,
+}
+
+impl ShellSessionCreateTool {
+    pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
+        let dir = match &self.working_directory {
+            Some(dir) => self.workspace.resolve_write(dir)?,
+            None => match self.workspace.root() {
+                Some(root) => root.to_path_buf(),
+                None => std::env::current_dir()?,
+            },
+        };
+
+        if !dir.is_dir() {
+            return Err(ToolError::InvalidArgument(format!(
+                "Working directory is not a directory: {}",
+                dir.display()
+            )));
+        }
+
+        let id = self
+            .sessions
+            .create(self.shell, &dir, &self.env, &self.limits)?;
+
+        let message = format!(
+            "Started shell session {} in {}. Use shell_session_run to run commands in it \
+             and shell_session_close when done.",
+            id,
+            dir.display()
+        );
+        Ok(CallToolResult::success(vec![Content::text(message)]))
+    }
+}
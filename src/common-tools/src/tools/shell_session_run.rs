@@ -0,0 +1,157 @@
+use rmcp::model::{CallToolResult, Content};
+use rmcp::service::{Peer, RoleServer};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use super::command_policy::enforce_decision;
+use super::execute_command::{cap_output, OutputFiles};
+use super::process::{combine_output, ProcessStatus};
+use super::shell_session::{SessionRunEnd, ShellSessions};
+use super::{CommandPolicy, ToolError};
+
+pub struct ShellSessionRunTool {
+    pub session_id: u32,
+    pub command: String,
+    pub timeout_ms: Option<u64>,
+    pub max_output_bytes: usize,
+    /// As for [`ExecuteCommandTool::output_dir`](super::ExecuteCommandTool::output_dir).
+    pub output_dir: Option<PathBuf>,
+    pub cancellation_token: CancellationToken,
+    pub sessions: ShellSessions,
+    pub policy: CommandPolicy,
+    /// Used to ask the user about commands the policy wants confirmed.
+    pub peer: Option<Peer<RoleServer>>,
+}
+
+/// The structured content of every `shell_session_run` result.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ShellCommandOutcome {
+    pub session_id: u32,
+    /// Exit status of the command, absent when it didn't finish.
+    pub exit_code: Option<i32>,
+    /// The session's working directory after the command.
+    pub working_directory: Option<String>,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+    pub timed_out: bool,
+    pub cancelled: bool,
+    /// The shell is gone, because the command exited it or it was killed
+    /// after a timeout or cancellation; the session id is no longer valid.
+    pub session_closed: bool,
+    #[serde(flatten)]
+    pub files: OutputFiles,
+}
+
+impl ShellCommandOutcome {
+    fn succeeded(&self) -> bool {
+        self.exit_code == Some(0) && !self.session_closed
+    }
+}
+
+impl ShellSessionRunTool {
+    pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
+        let dir = self.sessions.working_directory(self.session_id).await?;
+        let decision = self.policy.evaluate(&self.command, &dir);
+        enforce_decision(
+            &decision,
+            &self.command,
+            &dir,
+            self.peer.as_ref(),
+            &self.cancellation_token,
+        )
+        .await?;
+
+        let started = Instant::now();
+        let run = self
+            .sessions
+            .run(
+                self.session_id,
+                &self.command,
+                self.timeout_ms.map(Duration::from_millis),
+                &self.cancellation_token,
+            )
+            .await?;
+
+        let output_dir = self.output_dir.as_deref();
+        let (stdout, stdout_file) =
+            cap_output("stdout", run.stdout, self.max_output_bytes, output_dir)?;
+        let (stderr, stderr_file) =
+            cap_output("stderr", run.stderr, self.max_output_bytes, output_dir)?;
+
+        let files = OutputFiles {
+            stdout_file,
+            stderr_file,
+        };
+        let mut output = combine_output(&stdout, &stderr);
+        files.append_notes(&mut output);
+
+        let mut outcome = ShellCommandOutcome {
+            session_id: self.session_id,
+            exit_code: None,
+            working_directory: None,
+            stdout,
+            stderr,
+            duration_ms: started.elapsed().as_millis() as u64,
+            timed_out: false,
+            cancelled: false,
+            session_closed: true,
+            files,
+        };
+
+        let summary = match run.end {
+            SessionRunEnd::Finished {
+                exit_code,
+                working_directory,
+            } => {
+                outcome.exit_code = Some(exit_code);
+                outcome.working_directory = Some(working_directory.display().to_string());
+                outcome.session_closed = false;
+                if !output.is_empty() && !output.ends_with('\n') {
+                    output.push('\n');
+                }
+                format!(
+                    "{}[exit code {}, working directory {}]",
+                    output,
+                    exit_code,
+                    working_directory.display()
+                )
+            }
+            SessionRunEnd::ShellExited(status) => format!(
+                "The shell {}, so session {} is closed:\n{}",
+                ProcessStatus::from(status),
+                self.session_id,
+                output
+            ),
+            SessionRunEnd::TimedOut => {
+                outcome.timed_out = true;
+                format!(
+                    "Command timed out after {} ms and session {} was closed; partial output:\n{}",
+                    self.timeout_ms.unwrap_or_default(),
+                    self.session_id,
+                    output
+                )
+            }
+            SessionRunEnd::Cancelled => {
+                outcome.cancelled = true;
+                format!(
+                    "Command was cancelled and session {} was closed; partial output:\n{}",
+                    self.session_id, output
+                )
+            }
+        };
+
+        let content = vec![Content::text(summary)];
+        let mut result = if outcome.succeeded() {
+            CallToolResult::success(content)
+        } else {
+            CallToolResult::error(content)
+        };
+        result.structured_content =
+            Some(serde_json::to_value(&outcome).map_err(|e| ToolError::Other(e.to_string()))?);
+
+        Ok(result)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::tools::GrepTool;
+use crate::tools::{GrepTool, Workspace};
 use tempfile::TempDir;
 use tokio::fs;
 
@@ -27,6 +27,7 @@ async fn test_grep_basic_search() {
         offset: 0,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -56,6 +57,7 @@ async fn test_grep_case_sensitive() {
         offset: 0,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool_insensitive.call_tool().await.unwrap();
@@ -68,6 +70,7 @@ async fn test_grep_case_sensitive() {
         offset: 0,
         case_sensitive: true,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool_sensitive.call_tool().await.unwrap();
@@ -107,6 +110,7 @@ async fn test_grep_include_pattern() {
         offset: 0,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -137,6 +141,7 @@ async fn test_grep_pagination() {
         offset: 0,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -151,6 +156,7 @@ async fn test_grep_pagination() {
         offset: 20,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result_page2 = tool_page2.call_tool().await.unwrap();
@@ -177,6 +183,7 @@ async fn test_grep_no_matches() {
         offset: 0,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -196,6 +203,7 @@ async fn test_grep_invalid_regex() {
         offset: 0,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await;
@@ -220,6 +228,7 @@ async fn test_grep_context_lines() {
         offset: 0,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -270,6 +279,7 @@ async fn test_grep_respects_gitignore() {
         offset: 0,
         case_sensitive: false,
         working_directory: Some(temp_path.to_string_lossy().to_string()),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -1 +1,4 @@
+mod command_policy_test;
 mod grep_test;
+mod process_test;
+mod pty_test;
@@ -0,0 +1,67 @@
+use crate::tools::process::CapturedOutput;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Gives the reader task time to pick up what was just written.
+async fn settle() {
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+#[tokio::test]
+async fn test_take_new_holds_back_a_split_character() {
+    let (mut writer, pipe) = tokio::io::duplex(64);
+    let output = CapturedOutput::capture(Some(pipe), 1024);
+
+    writer
+        .write_all(&"caf\u{e9}".as_bytes()[..4])
+        .await
+        .unwrap();
+    settle().await;
+    assert_eq!(output.take_new(), "caf");
+
+    writer.write_all(&"\u{e9}".as_bytes()[1..]).await.unwrap();
+    settle().await;
+    assert_eq!(output.take_new(), "\u{e9}");
+
+    writer.write_all(&"\u{e9}".as_bytes()[..1]).await.unwrap();
+    drop(writer);
+    settle().await;
+    assert_eq!(output.take_new(), "\u{fffd}");
+}
+
+#[tokio::test]
+async fn test_read_since_holds_back_a_split_character() {
+    let (mut writer, pipe) = tokio::io::duplex(64);
+    let output = CapturedOutput::capture(Some(pipe), 1024);
+
+    writer
+        .write_all(&"a\u{20ac}".as_bytes()[..3])
+        .await
+        .unwrap();
+    settle().await;
+    let (text, offset) = output.read_since(0);
+    assert_eq!((text.as_str(), offset), ("a", 1));
+
+    writer.write_all(&"\u{20ac}".as_bytes()[2..]).await.unwrap();
+    settle().await;
+    let (text, offset) = output.read_since(offset);
+    assert_eq!((text.as_str(), offset), ("\u{20ac}", 4));
+}
+
+#[tokio::test]
+async fn test_dropping_output_keeps_whole_characters() {
+    let (mut writer, pipe) = tokio::io::duplex(64);
+    let output = CapturedOutput::capture(Some(pipe), 5);
+
+    // Keeping the last 5 bytes would start inside the first "é".
+    writer
+        .write_all("\u{e9}\u{e9}\u{e9}".as_bytes())
+        .await
+        .unwrap();
+    drop(writer);
+    settle().await;
+    assert_eq!(
+        output.take_new(),
+        "… 2 earlier bytes dropped …\n\u{e9}\u{e9}"
+    );
+}
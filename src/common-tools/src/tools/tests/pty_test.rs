@@ -0,0 +1,22 @@
+use crate::tools::pty::render_terminal_output;
+
+#[test]
+fn test_render_strips_colors_and_crlf() {
+    let raw = "\x1b[1;32mCompiling\x1b[0m foo v0.1.0\r\n\x1b]0;window title\x07done\r\n";
+    assert_eq!(render_terminal_output(raw), "Compiling foo v0.1.0\ndone\n");
+}
+
+#[test]
+fn test_render_applies_carriage_returns() {
+    let raw = "progress  10%\rprogress  50%\rprogress 100%\r\nnext\n";
+    assert_eq!(render_terminal_output(raw), "progress 100%\nnext\n");
+
+    let raw = "downloading... 3/3\r\x1b[Kfinished\n";
+    assert_eq!(render_terminal_output(raw), "finished\n");
+}
+
+#[test]
+fn test_render_applies_backspaces() {
+    assert_eq!(render_terminal_output("abd\x08c\n"), "abc\n");
+    assert_eq!(render_terminal_output("x\x08\x08\x08y"), "y");
+}
@@ -0,0 +1,157 @@
+use std::path::Path;
+use tokio::fs;
+
+use super::ToolError;
+
+const BOM: char = '\u{feff}';
+
+/// How lines end in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LineEnding {
+    /// `\n`. Also used for files that mix endings, whose stray `\r`
+    /// characters are then left alone as part of their lines.
+    Lf,
+    /// `\r\n` on every line.
+    CrLf,
+}
+
+/// A text file's contents, with the details editing tools must not disturb
+/// kept apart from the text they work on.
+///
+/// `text` has no byte order mark and uses `\n` line endings; `render` puts
+/// the file's own conventions back, so an edit only changes the lines it
+/// touches.
+#[derive(Debug, Clone)]
+pub(crate) struct TextDocument {
+    pub(crate) text: String,
+    bom: bool,
+    line_ending: LineEnding,
+    final_newline: bool,
+}
+
+impl TextDocument {
+    pub(crate) fn parse(raw: &str) -> Self {
+        let (bom, raw) = match raw.strip_prefix(BOM) {
+            Some(rest) => (true, rest),
+            None => (false, raw),
+        };
+
+        let line_breaks = raw.matches('\n').count();
+        let crlf_breaks = raw.matches("\r\n").count();
+        let line_ending = if line_breaks > 0 && crlf_breaks == line_breaks {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        };
+
+        let text = match line_ending {
+            LineEnding::CrLf => raw.replace("\r\n", "\n"),
+            LineEnding::Lf => raw.to_string(),
+        };
+
+        Self {
+            final_newline: text.ends_with('\n'),
+            text,
+            bom,
+            line_ending,
+        }
+    }
+
+    pub(crate) async fn read(path: &Path) -> Result<Self, ToolError> {
+        let raw = fs::read_to_string(path).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                ToolError::PermissionDenied(path.display().to_string())
+            } else {
+                ToolError::Io(e)
+            }
+        })?;
+        Ok(Self::parse(&raw))
+    }
+
+    /// The lines of `text`, not counting the empty one after a final newline.
+    pub(crate) fn lines(&self) -> Vec<&str> {
+        if self.text.is_empty() {
+            return Vec::new();
+        }
+        let body = self.text.strip_suffix('\n').unwrap_or(&self.text);
+        body.split('\n').collect()
+    }
+
+    /// Lines `start..end`, counting from 0, exactly as the file stores them:
+    /// with its line endings and byte order mark, and no newline after a
+    /// last line that lacks one.
+    pub(crate) fn raw_lines(&self, start: usize, end: usize) -> String {
+        let lines = self.lines();
+        let line_break = match self.line_ending {
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Lf => "\n",
+        };
+
+        let mut raw = String::new();
+        if self.bom && start == 0 {
+            raw.push(BOM);
+        }
+        for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+            raw.push_str(line);
+            if i + 1 < lines.len() || self.final_newline {
+                raw.push_str(line_break);
+            }
+        }
+        raw
+    }
+
+    /// Whether edited lines are joined with a newline at the end: if the file
+    /// had one, or is empty, since text added to an empty file ends with one.
+    pub(crate) fn final_newline(&self) -> bool {
+        self.final_newline || self.text.is_empty()
+    }
+
+    /// Joins edited lines back into text, ending with a newline if the file
+    /// did. Text added to an empty file ends with one.
+    pub(crate) fn join_lines<S: AsRef<str>>(&self, lines: &[S]) -> String {
+        self.join_lines_ending(lines, self.final_newline())
+    }
+
+    /// Joins edited lines back into text, ending with a newline if
+    /// `final_newline` is set.
+    pub(crate) fn join_lines_ending<S: AsRef<str>>(
+        &self,
+        lines: &[S],
+        final_newline: bool,
+    ) -> String {
+        let mut text = lines
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !lines.is_empty() && final_newline {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Converts edited `text` back to the file's byte order mark and line
+    /// endings.
+    pub(crate) fn render(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len() + 3);
+        if self.bom {
+            output.push(BOM);
+        }
+        match self.line_ending {
+            LineEnding::CrLf => output.push_str(&text.replace("\r\n", "\n").replace('\n', "\r\n")),
+            LineEnding::Lf => output.push_str(text),
+        }
+        output
+    }
+
+    /// Fits whole new contents for the file to its conventions: its line
+    /// endings and byte order mark, and a final newline if it had one.
+    pub(crate) fn adopt(&self, content: &str) -> String {
+        let content = content.strip_prefix(BOM).unwrap_or(content);
+        let mut text = content.replace("\r\n", "\n");
+        if self.final_newline && !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.render(&text)
+    }
+}
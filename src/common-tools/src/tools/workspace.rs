@@ -0,0 +1,163 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use super::{resolve_path, ToolError};
+
+#[derive(Debug)]
+struct WorkspaceRoots {
+    root: PathBuf,
+    read_only_roots: Vec<PathBuf>,
+}
+
+/// The set of directories tools are allowed to touch.
+///
+/// An unrestricted workspace (the default) resolves relative paths against the
+/// current directory and accepts any path. A sandboxed workspace resolves
+/// relative paths against its root and rejects anything that escapes it once
+/// `..` and symlinks have been resolved. Extra read-only roots may be read from
+/// but never written to.
+#[derive(Debug, Clone, Default)]
+pub struct Workspace {
+    roots: Option<Arc<WorkspaceRoots>>,
+}
+
+impl Workspace {
+    pub fn unrestricted() -> Self {
+        Self::default()
+    }
+
+    pub fn new(root: impl AsRef<Path>, read_only_roots: &[PathBuf]) -> Result<Self, ToolError> {
+        let root = canonicalize_root(root.as_ref())?;
+        let read_only_roots = read_only_roots
+            .iter()
+            .map(|p| canonicalize_root(p))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            roots: Some(Arc::new(WorkspaceRoots {
+                root,
+                read_only_roots,
+            })),
+        })
+    }
+
+    /// The same workspace, also allowed to read from `dir`. An unrestricted
+    /// workspace can already read it and is returned as it is.
+    pub fn with_read_only_root(&self, dir: impl AsRef<Path>) -> Result<Self, ToolError> {
+        let Some(roots) = &self.roots else {
+            return Ok(self.clone());
+        };
+
+        let mut read_only_roots = roots.read_only_roots.clone();
+        read_only_roots.push(canonicalize_root(dir.as_ref())?);
+        Ok(Self {
+            roots: Some(Arc::new(WorkspaceRoots {
+                root: roots.root.clone(),
+                read_only_roots,
+            })),
+        })
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.roots.as_ref().map(|r| r.root.as_path())
+    }
+
+    /// Resolves a path that will only be read from.
+    pub fn resolve_read(&self, path: impl AsRef<Path>) -> Result<PathBuf, ToolError> {
+        self.resolve(path.as_ref(), true)
+    }
+
+    /// Resolves a path that may be created, modified or removed.
+    pub fn resolve_write(&self, path: impl AsRef<Path>) -> Result<PathBuf, ToolError> {
+        self.resolve(path.as_ref(), false)
+    }
+
+    /// Both the path itself and whatever it refers to once symlinks are
+    /// followed must be allowed. The path returned keeps its final component
+    /// as given, so a tool moving or deleting a symlink acts on the link
+    /// rather than its target.
+    fn resolve(&self, path: &Path, read_only: bool) -> Result<PathBuf, ToolError> {
+        let Some(roots) = &self.roots else {
+            return Ok(resolve_path(path, None));
+        };
+
+        let joined = resolve_path(path, Some(&roots.root));
+        let located = match (joined.parent(), joined.file_name()) {
+            (Some(parent), Some(name)) => canonicalize_lenient(parent)?.join(name),
+            _ => canonicalize_lenient(&joined)?,
+        };
+        let target = canonicalize_lenient(&joined)?;
+
+        let allowed = |path: &Path| {
+            path.starts_with(&roots.root)
+                || (read_only && roots.read_only_roots.iter().any(|r| path.starts_with(r)))
+        };
+        if allowed(&located) && allowed(&target) {
+            return Ok(located);
+        }
+
+        Err(ToolError::OutsideWorkspace(format!(
+            "{} (workspace root: {})",
+            joined.display(),
+            roots.root.display()
+        )))
+    }
+}
+
+fn canonicalize_root(path: &Path) -> Result<PathBuf, ToolError> {
+    let abs_path = resolve_path(path, None);
+    abs_path.canonicalize().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            ToolError::FileNotFound(abs_path.display().to_string())
+        } else {
+            ToolError::Io(e)
+        }
+    })
+}
+
+/// Most symlinks followed while resolving one path, as with `SYMLOOP_MAX`.
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// Canonicalizes the longest existing prefix of `path` and appends the rest
+/// lexically, so paths that are about to be created can still be checked.
+fn canonicalize_lenient(path: &Path) -> Result<PathBuf, ToolError> {
+    canonicalize_within(path, MAX_SYMLINK_DEPTH)
+}
+
+fn canonicalize_within(path: &Path, depth: usize) -> Result<PathBuf, ToolError> {
+    let components: Vec<Component> = path.components().collect();
+    let unresolvable =
+        |p: &Path| ToolError::InvalidArgument(format!("Cannot resolve path: {}", p.display()));
+
+    for split in (1..=components.len()).rev() {
+        let prefix: PathBuf = components[..split].iter().collect();
+        let Ok(metadata) = prefix.symlink_metadata() else {
+            continue;
+        };
+
+        let mut resolved = match prefix.canonicalize() {
+            Ok(resolved) => resolved,
+            // A dangling symlink: check where it would create a file.
+            Err(_) if metadata.file_type().is_symlink() && depth > 0 => {
+                let target = std::fs::read_link(&prefix).map_err(|_| unresolvable(&prefix))?;
+                let base = prefix.parent().unwrap_or(Path::new("/"));
+                canonicalize_within(&base.join(target), depth - 1)?
+            }
+            Err(_) => return Err(unresolvable(&prefix)),
+        };
+
+        for component in &components[split..] {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                _ => {}
+            }
+        }
+
+        return Ok(resolved);
+    }
+
+    Ok(path.to_path_buf())
+}
@@ -1,16 +1,18 @@
 use rmcp::model::{CallToolResult, Content};
 use tokio::fs;
 
-use super::{resolve_path, ToolError};
+use super::text_document::TextDocument;
+use super::{ToolError, Workspace};
 
 pub struct WriteFileTool {
     pub path: String,
     pub content: String,
+    pub workspace: Workspace,
 }
 
 impl WriteFileTool {
     pub async fn call_tool(&self) -> Result<CallToolResult, ToolError> {
-        let abs_path = resolve_path(&self.path, None);
+        let abs_path = self.workspace.resolve_write(&self.path)?;
 
         if let Some(parent) = abs_path.parent() {
             if !parent.exists() {
@@ -24,7 +26,15 @@ impl WriteFileTool {
             }
         }
 
-        fs::write(&abs_path, &self.content).await.map_err(|e| {
+        // Overwriting a file keeps its line endings, byte order mark and
+        // final newline, so rewriting it doesn't show up as a change to
+        // every line.
+        let content = match fs::read_to_string(&abs_path).await {
+            Ok(existing) => TextDocument::parse(&existing).adopt(&self.content),
+            Err(_) => self.content.clone(),
+        };
+
+        fs::write(&abs_path, &content).await.map_err(|e| {
             if e.kind() == std::io::ErrorKind::PermissionDenied {
                 ToolError::PermissionDenied(abs_path.display().to_string())
             } else {
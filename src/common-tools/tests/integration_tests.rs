@@ -1,17 +1,66 @@
-use common_tools::CommonToolsServer;
+use common_tools::{CommandPolicy, CommonToolsServer, ServerConfig, Workspace};
 use rmcp::{
-    model::{CallToolRequestParams, ClientRequest, Request, ServerResult},
-    ClientHandler, ServiceExt,
+    model::{
+        CallToolRequestParams, ClientInfo, ClientRequest, CreateElicitationRequestParams,
+        CreateElicitationResult, ElicitationAction, ElicitationCapability,
+        ProgressNotificationParam, Request, ServerResult,
+    },
+    service::{NotificationContext, RequestContext, RoleClient},
+    transport::StreamableHttpClientTransport,
+    ClientHandler, ErrorData as McpError, ServiceExt,
 };
 use serde_json::json;
+use std::sync::{Arc, Mutex};
 use tempfile::TempDir;
 use tokio::fs;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Default, Clone)]
 struct TestClient;
 
 impl ClientHandler for TestClient {}
 
+/// A client that records the progress notifications it receives.
+#[derive(Default, Clone)]
+struct ProgressClient {
+    progress: Arc<Mutex<Vec<ProgressNotificationParam>>>,
+}
+
+impl ClientHandler for ProgressClient {
+    async fn on_progress(
+        &self,
+        params: ProgressNotificationParam,
+        _context: NotificationContext<RoleClient>,
+    ) {
+        self.progress.lock().unwrap().push(params);
+    }
+}
+
+/// A client that answers every elicitation request with a fixed approval.
+#[derive(Clone)]
+struct ConfirmingClient {
+    approve: bool,
+}
+
+impl ClientHandler for ConfirmingClient {
+    async fn create_elicitation(
+        &self,
+        _request: CreateElicitationRequestParams,
+        _context: RequestContext<RoleClient>,
+    ) -> Result<CreateElicitationResult, McpError> {
+        Ok(CreateElicitationResult {
+            action: ElicitationAction::Accept,
+            content: Some(json!({ "approve": self.approve })),
+        })
+    }
+
+    fn get_info(&self) -> ClientInfo {
+        let mut info = ClientInfo::default();
+        info.capabilities.elicitation = Some(ElicitationCapability::default());
+        info
+    }
+}
+
 #[tokio::test]
 async fn test_server_list_tools() -> anyhow::Result<()> {
     let server = CommonToolsServer::new();
@@ -32,7 +81,7 @@ async fn test_server_list_tools() -> anyhow::Result<()> {
         panic!("expected list tools result, got {response:?}");
     };
 
-    assert_eq!(result.tools.len(), 12);
+    assert_eq!(result.tools.len(), 20);
 
     let tool_names: Vec<&str> = result.tools.iter().map(|t| t.name.as_ref()).collect();
     assert!(tool_names.contains(&"read_file"));
@@ -45,8 +94,16 @@ async fn test_server_list_tools() -> anyhow::Result<()> {
     assert!(tool_names.contains(&"move_path"));
     assert!(tool_names.contains(&"now"));
     assert!(tool_names.contains(&"search_replace_edit"));
+    assert!(tool_names.contains(&"edit_lines"));
     assert!(tool_names.contains(&"apply_patch"));
     assert!(tool_names.contains(&"task_complete"));
+    assert!(tool_names.contains(&"process_output"));
+    assert!(tool_names.contains(&"process_write_stdin"));
+    assert!(tool_names.contains(&"process_kill"));
+    assert!(tool_names.contains(&"process_list"));
+    assert!(tool_names.contains(&"shell_session_create"));
+    assert!(tool_names.contains(&"shell_session_run"));
+    assert!(tool_names.contains(&"shell_session_close"));
 
     client_service.cancel().await?;
     Ok(())
@@ -199,6 +256,48 @@ async fn test_server_call_execute_command_tool() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[cfg(unix)]
+#[tokio::test]
+async fn test_server_call_execute_command_with_program_and_args() -> anyhow::Result<()> {
+    let server = CommonToolsServer::new();
+    let client = TestClient;
+    let (server_transport, client_transport) = tokio::io::duplex(8192);
+    let _server_handle = tokio::spawn(async move {
+        let service = server.serve(server_transport).await?;
+        service.waiting().await?;
+        anyhow::Ok(())
+    });
+    let client_service = client.serve(client_transport).await?;
+
+    let params = CallToolRequestParams {
+        name: "execute_command".into(),
+        arguments: Some(
+            json!({
+                "program": "echo",
+                "args": ["no $expansion", "'quotes'"]
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ),
+        meta: None,
+        task: None,
+    };
+
+    let response = client_service
+        .send_request(ClientRequest::CallToolRequest(Request::new(params)))
+        .await?;
+
+    let ServerResult::CallToolResult(result) = response else {
+        panic!("expected call tool result, got {response:?}");
+    };
+    let stdout = result.structured_content.unwrap()["stdout"].clone();
+    assert_eq!(stdout, json!("no $expansion 'quotes'\n"));
+
+    client_service.cancel().await?;
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_server_call_list_directory_tool() -> anyhow::Result<()> {
     let server = CommonToolsServer::new();
@@ -538,3 +637,288 @@ async fn test_integration_file_workflow() -> anyhow::Result<()> {
     client_service.cancel().await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn test_http_transport_serves_multiple_sessions() -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!(
+        "http://{}{}",
+        listener.local_addr()?,
+        common_tools::http::MCP_ENDPOINT
+    );
+    let shutdown = CancellationToken::new();
+    let server_handle = tokio::spawn(common_tools::http::serve_http(
+        listener,
+        ServerConfig::default(),
+        shutdown.clone(),
+    ));
+
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("http.txt");
+    fs::write(&file_path, "Served over HTTP").await.unwrap();
+
+    let first = TestClient
+        .serve(StreamableHttpClientTransport::from_uri(url.as_str()))
+        .await?;
+    let second = TestClient
+        .serve(StreamableHttpClientTransport::from_uri(url.as_str()))
+        .await?;
+
+    for client_service in [&first, &second] {
+        let params = CallToolRequestParams {
+            name: "read_file".into(),
+            arguments: Some(
+                json!({
+                    "path": file_path.to_string_lossy()
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+            meta: None,
+            task: None,
+        };
+
+        let response = client_service
+            .send_request(ClientRequest::CallToolRequest(Request::new(params)))
+            .await?;
+
+        let ServerResult::CallToolResult(result) = response else {
+            panic!("expected call tool result, got {response:?}");
+        };
+
+        let content_str = format!("{:?}", result.content[0]);
+        assert!(content_str.contains("Served over HTTP"));
+    }
+
+    // Background processes belong to the session that started them.
+    #[cfg(unix)]
+    {
+        let call = |name: &'static str, arguments: serde_json::Value| {
+            ClientRequest::CallToolRequest(Request::new(CallToolRequestParams {
+                name: name.into(),
+                arguments: Some(arguments.as_object().unwrap().clone()),
+                meta: None,
+                task: None,
+            }))
+        };
+
+        first
+            .send_request(call(
+                "execute_command",
+                json!({ "command": "sleep 30", "background": true }),
+            ))
+            .await?;
+
+        let mut listings = Vec::new();
+        for client_service in [&first, &second] {
+            let response = client_service
+                .send_request(call("process_list", json!({})))
+                .await?;
+            let ServerResult::CallToolResult(result) = response else {
+                panic!("expected call tool result, got {response:?}");
+            };
+            listings.push(format!("{:?}", result.content[0]));
+        }
+        assert!(listings[0].contains("sleep 30"), "{}", listings[0]);
+        assert!(!listings[1].contains("sleep 30"), "{}", listings[1]);
+    }
+
+    first.cancel().await?;
+    second.cancel().await?;
+
+    shutdown.cancel();
+    tokio::time::timeout(std::time::Duration::from_secs(5), server_handle).await???;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_confirmation_through_elicitation() -> anyhow::Result<()> {
+    let config = ServerConfig {
+        command_policy: CommandPolicy::from_toml(
+            "[[rules]]\naction = \"confirm\"\nprogram = \"echo\"",
+        )?,
+        ..ServerConfig::default()
+    };
+
+    for approve in [true, false] {
+        let server = CommonToolsServer::with_config(config.clone());
+        let (server_transport, client_transport) = tokio::io::duplex(8192);
+        let _server_handle = tokio::spawn(async move {
+            let service = server.serve(server_transport).await?;
+            service.waiting().await?;
+            anyhow::Ok(())
+        });
+        let client_service = ConfirmingClient { approve }.serve(client_transport).await?;
+
+        let params = CallToolRequestParams {
+            name: "execute_command".into(),
+            arguments: Some(
+                json!({ "command": "echo confirmed" })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ),
+            meta: None,
+            task: None,
+        };
+
+        let response = client_service
+            .send_request(ClientRequest::CallToolRequest(Request::new(params)))
+            .await;
+
+        if approve {
+            let Ok(ServerResult::CallToolResult(result)) = response else {
+                panic!("expected call tool result, got {response:?}");
+            };
+            assert!(format!("{:?}", result.content[0]).contains("confirmed"));
+        } else {
+            let error = response.expect_err("declined command should fail");
+            assert!(error.to_string().contains("did not approve"));
+        }
+
+        client_service.cancel().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_streams_progress() -> anyhow::Result<()> {
+    let server = CommonToolsServer::new();
+    let client = ProgressClient::default();
+    let progress = client.progress.clone();
+    let (server_transport, client_transport) = tokio::io::duplex(8192);
+    let _server_handle = tokio::spawn(async move {
+        let service = server.serve(server_transport).await?;
+        service.waiting().await?;
+        anyhow::Ok(())
+    });
+    let client_service = client.serve(client_transport).await?;
+
+    // The client attaches a progress token to every request it sends.
+    let params = CallToolRequestParams {
+        name: "execute_command".into(),
+        arguments: Some(
+            json!({ "command": "for i in 1 2 3; do echo step $i; sleep 0.7; done" })
+                .as_object()
+                .unwrap()
+                .clone(),
+        ),
+        meta: None,
+        task: None,
+    };
+
+    let response = client_service
+        .send_request(ClientRequest::CallToolRequest(Request::new(params)))
+        .await?;
+
+    let ServerResult::CallToolResult(result) = response else {
+        panic!("expected call tool result, got {response:?}");
+    };
+    assert_eq!(
+        result.structured_content.unwrap()["stdout"],
+        json!("step 1\nstep 2\nstep 3\n")
+    );
+
+    let progress = progress.lock().unwrap().clone();
+    assert!(!progress.is_empty(), "no progress notifications received");
+    assert!(progress
+        .iter()
+        .all(|p| p.progress_token == progress[0].progress_token));
+    assert!(progress.windows(2).all(|w| w[0].progress < w[1].progress));
+    assert!(progress[0].message.as_deref().unwrap().contains("step 1"));
+
+    client_service.cancel().await?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_truncated_output_is_readable_and_removed_with_the_server() -> anyhow::Result<()> {
+    let workspace_dir = TempDir::new().unwrap();
+    let config = ServerConfig {
+        workspace: Workspace::new(workspace_dir.path(), &[])?,
+        ..ServerConfig::default()
+    };
+    let server = CommonToolsServer::with_config(config);
+    let (server_transport, client_transport) = tokio::io::duplex(8192);
+    let server_handle = tokio::spawn(async move {
+        let service = server.serve(server_transport).await?;
+        service.waiting().await?;
+        anyhow::Ok(())
+    });
+    let client_service = TestClient.serve(client_transport).await?;
+
+    let call = |name: &'static str, arguments: serde_json::Value| CallToolRequestParams {
+        name: name.into(),
+        arguments: Some(arguments.as_object().unwrap().clone()),
+        meta: None,
+        task: None,
+    };
+
+    let response = client_service
+        .send_request(ClientRequest::CallToolRequest(Request::new(call(
+            "execute_command",
+            json!({ "command": "seq 1 10000", "max_output_bytes": 200 }),
+        ))))
+        .await?;
+    let ServerResult::CallToolResult(result) = response else {
+        panic!("expected call tool result, got {response:?}");
+    };
+    let stdout_file = result.structured_content.unwrap()["stdout_file"]
+        .as_str()
+        .expect("full output should be spilled")
+        .to_string();
+    assert!(!stdout_file.starts_with(&*workspace_dir.path().to_string_lossy()));
+
+    let response = client_service
+        .send_request(ClientRequest::CallToolRequest(Request::new(call(
+            "read_file",
+            json!({ "path": stdout_file, "limit": 1 }),
+        ))))
+        .await?;
+    let ServerResult::CallToolResult(result) = response else {
+        panic!("expected call tool result, got {response:?}");
+    };
+    assert!(format!("{:?}", result.content[0]).contains('1'));
+
+    client_service.cancel().await?;
+    tokio::time::timeout(std::time::Duration::from_secs(5), server_handle).await???;
+    assert!(!std::path::Path::new(&stdout_file).exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_http_server_exits_cleanly_on_sigterm() -> anyhow::Result<()> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let mut server = tokio::process::Command::new(env!("CARGO_BIN_EXE_common-tools"))
+        .args(["--transport", "http", "--bind", "127.0.0.1:0"])
+        .env("RUST_LOG", "info")
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut log = BufReader::new(server.stderr.take().unwrap()).lines();
+    let listening = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+        while let Some(line) = log.next_line().await? {
+            if line.contains("Listening on") {
+                return Ok(true);
+            }
+        }
+        anyhow::Ok(false)
+    })
+    .await??;
+    assert!(listening, "server exited before listening");
+
+    let pid = server.id().unwrap() as libc::pid_t;
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGTERM) }, 0);
+
+    let status = tokio::time::timeout(std::time::Duration::from_secs(5), server.wait()).await??;
+    assert!(status.success(), "{status}");
+    Ok(())
+}
@@ -1,6 +1,8 @@
 use common_tools::tools::*;
+use std::collections::HashMap;
 use std::fs;
 use tempfile::TempDir;
+use tokio_util::sync::CancellationToken;
 
 #[tokio::test]
 async fn test_read_file_tool() {
@@ -12,6 +14,12 @@ async fn test_read_file_tool() {
 
     let tool = ReadFileTool {
         path: file_path.to_string_lossy().to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -21,6 +29,215 @@ async fn test_read_file_tool() {
     assert!(content_str.contains("Hello, world!"));
 }
 
+#[tokio::test]
+async fn test_read_file_tool_pagination() {
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("long.txt");
+    let content: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+
+    fs::write(&file_path, content).unwrap();
+
+    let tool = ReadFileTool {
+        path: file_path.to_string_lossy().to_string(),
+        offset: 4,
+        limit: 3,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+
+    assert!(text.starts_with("Lines 4-6 of 10 total:"));
+    assert!(text.contains("     4\tline 4\n"));
+    assert!(text.contains("     6\tline 6\n"));
+    assert!(!text.contains("\tline 7"));
+    assert!(text.contains("truncated, continue at line 7"));
+}
+
+#[tokio::test]
+async fn test_read_file_tool_max_bytes() {
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("wide.txt");
+    let content: String = (1..=10).map(|i| format!("{:>20}\n", i)).collect();
+
+    fs::write(&file_path, content).unwrap();
+
+    let tool = ReadFileTool {
+        path: file_path.to_string_lossy().to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: 60,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+
+    assert!(text.starts_with("Lines 1-2 of 10 total:"));
+    assert!(text.contains("continue at line 3"));
+}
+
+#[tokio::test]
+async fn test_read_file_tool_offset_past_end() {
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("short.txt");
+
+    fs::write(&file_path, "only line\n").unwrap();
+
+    let tool = ReadFileTool {
+        path: file_path.to_string_lossy().to_string(),
+        offset: 5,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::InvalidArgument(_))));
+}
+
+#[tokio::test]
+async fn test_read_file_tool_binary_summary() {
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("program.bin");
+    let mut bytes = b"\x7fELF\x02\x01\x01\0".to_vec();
+    bytes.extend(std::iter::repeat_n(0u8, 64));
+
+    fs::write(&file_path, &bytes).unwrap();
+
+    let tool = ReadFileTool {
+        path: file_path.to_string_lossy().to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+
+    assert!(text.contains("Binary file:"));
+    assert!(text.contains("Size: 72 bytes"));
+    assert!(text.contains("MIME type: application/x-elf"));
+    assert!(text.contains("00000000  7f 45 4c 46 02 01 01 00"));
+    assert!(text.contains("|.ELF"));
+}
+
+#[tokio::test]
+async fn test_read_file_tool_image_content() {
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("screenshot.png");
+    image::RgbImage::from_pixel(100, 50, image::Rgb([200, 30, 30]))
+        .save(&file_path)
+        .unwrap();
+
+    let tool = ReadFileTool {
+        path: file_path.to_string_lossy().to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    assert_eq!(result.content.len(), 2);
+    let summary = result.content[0].as_text().unwrap().text.clone();
+    assert!(summary.contains("100x50, image/png"));
+    let image_content = result.content[1].as_image().unwrap();
+    assert_eq!(image_content.mime_type, "image/png");
+
+    let downscaled = ReadFileTool {
+        max_image_dimension: Some(20),
+        ..tool
+    };
+    let result = downscaled.call_tool().await.unwrap();
+    let summary = result.content[0].as_text().unwrap().text.clone();
+    assert!(summary.contains("20x10, image/png"));
+    assert!(summary.contains("downscaled from 100x50"));
+}
+
+#[tokio::test]
+async fn test_read_file_tool_downscales_images_too_large_to_embed() {
+    use base64::Engine;
+    use common_tools::tools::read_file::MAX_EMBEDDED_BYTES;
+
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("noise.png");
+    // Noise barely compresses, so this fits 1568 pixels but not 5 MiB.
+    let mut state = 0x2545_f491_u32;
+    image::RgbImage::from_fn(1400, 1400, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let [r, g, b, _] = state.to_le_bytes();
+        image::Rgb([r, g, b])
+    })
+    .save(&file_path)
+    .unwrap();
+    assert!(fs::metadata(&file_path).unwrap().len() > MAX_EMBEDDED_BYTES as u64);
+
+    let tool = ReadFileTool {
+        path: file_path.to_string_lossy().to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: Some(2000),
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    let summary = result.content[0].as_text().unwrap().text.clone();
+    assert!(summary.contains("downscaled from 1400x1400"), "{}", summary);
+    let image_content = result.content[1].as_image().unwrap();
+    let embedded = base64::engine::general_purpose::STANDARD
+        .decode(&image_content.data)
+        .unwrap();
+    assert!(embedded.len() <= MAX_EMBEDDED_BYTES);
+}
+
+#[tokio::test]
+async fn test_read_file_tool_legacy_encoding() {
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("latin1.txt");
+
+    fs::write(&file_path, b"caf\xe9 cr\xe8me\n").unwrap();
+
+    let lossy = ReadFileTool {
+        path: file_path.to_string_lossy().to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::default(),
+    };
+    let result = lossy.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+    assert!(text.contains("decoded lossily"));
+    assert!(text.contains("caf\u{fffd}"));
+
+    let declared = ReadFileTool {
+        encoding: Some("latin1".to_string()),
+        ..lossy
+    };
+    let result = declared.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+    assert!(!text.contains("decoded lossily"));
+    assert!(text.contains("café crème"));
+}
+
 #[tokio::test]
 async fn test_write_file_tool() {
     let temp_dir = TempDir::new().unwrap();
@@ -30,6 +247,7 @@ async fn test_write_file_tool() {
     let tool = WriteFileTool {
         path: file_path.to_string_lossy().to_string(),
         content: content.to_string(),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -52,6 +270,7 @@ async fn test_list_directory_tool() {
 
     let tool = ListDirectoryTool {
         path: temp_dir.path().to_string_lossy().to_string(),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -71,6 +290,7 @@ async fn test_create_directory_tool() {
 
     let tool = CreateDirectoryTool {
         path: new_dir_path.to_string_lossy().to_string(),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -94,6 +314,7 @@ async fn test_copy_path_tool() {
     let tool = CopyPathTool {
         source_path: source_file.to_string_lossy().to_string(),
         destination_path: dest_file.to_string_lossy().to_string(),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -119,6 +340,7 @@ async fn test_move_path_tool() {
     let tool = MovePathTool {
         source_path: source_file.to_string_lossy().to_string(),
         destination_path: dest_file.to_string_lossy().to_string(),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -163,15 +385,37 @@ async fn test_now_tool_local() {
     assert!(content_str.contains("T"));
 }
 
+/// An `execute_command` call with default settings and no limits beyond
+/// them; the other command helpers start from it.
+fn command_tool(invocation: Invocation) -> ExecuteCommandTool {
+    ExecuteCommandTool {
+        invocation,
+        working_directory: None,
+        env: HashMap::new(),
+        env_clear: false,
+        stdin: None,
+        pty: None,
+        timeout_ms: None,
+        limits: ResourceLimits::default(),
+        max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        output_dir: None,
+        background: false,
+        cancellation_token: CancellationToken::new(),
+        workspace: Workspace::default(),
+        processes: ProcessRegistry::new(),
+        policy: CommandPolicy::allow_all(),
+        peer: None,
+        progress_token: None,
+    }
+}
+
 #[tokio::test]
 async fn test_execute_command_tool() {
-    let tool = ExecuteCommandTool {
-        command: if cfg!(target_os = "windows") {
-            "echo Hello".to_string()
-        } else {
-            "echo 'Hello'".to_string()
-        },
-    };
+    let tool = command_tool(Invocation::shell(if cfg!(target_os = "windows") {
+        "echo Hello"
+    } else {
+        "echo 'Hello'"
+    }));
 
     let result = tool.call_tool().await.unwrap();
 
@@ -189,11 +433,16 @@ async fn test_search_replace_edit_tool() {
     fs::write(&file_path, original_content).unwrap();
 
     let tool = SearchReplaceEditTool {
-        path: file_path.to_string_lossy().to_string(),
-        edits: vec![EditOperation {
-            search: "Hello".to_string(),
-            replace: "Hi".to_string(),
+        files: vec![FileEdits {
+            path: file_path.to_string_lossy().to_string(),
+            edits: vec![EditOperation {
+                search: "Hello".to_string(),
+                replace: "Hi".to_string(),
+                ..Default::default()
+            }],
         }],
+        dry_run: false,
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await.unwrap();
@@ -207,6 +456,653 @@ async fn test_search_replace_edit_tool() {
     assert_eq!(edited_content, "Hi world\nThis is a test\nGoodbye world");
 }
 
+/// Writes `content` to a file in a new temporary directory and runs `tool` on
+/// its path. Returns the file's contents afterwards along with the tool's
+/// result, or the error once it has checked the file was left as it was.
+async fn with_temp_file<T, F, Fut>(content: &str, tool: F) -> Result<(String, T), String>
+where
+    F: FnOnce(String) -> Fut,
+    Fut: std::future::Future<Output = Result<T, ToolError>>,
+{
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("file.txt");
+    fs::write(&path, content).unwrap();
+
+    match tool(path.to_string_lossy().to_string()).await {
+        Ok(result) => Ok((fs::read_to_string(&path).unwrap(), result)),
+        Err(e) => {
+            assert_eq!(fs::read_to_string(&path).unwrap(), content);
+            Err(e.to_string())
+        }
+    }
+}
+
+fn text_edit(search: &str, replace: &str) -> EditOperation {
+    EditOperation {
+        search: search.to_string(),
+        replace: replace.to_string(),
+        ..Default::default()
+    }
+}
+
+fn file_edits(path: &std::path::Path, search: &str, replace: &str) -> FileEdits {
+    FileEdits {
+        path: path.to_string_lossy().to_string(),
+        edits: vec![text_edit(search, replace)],
+    }
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_multiple_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let first = temp_dir.path().join("first.rs");
+    let second = temp_dir.path().join("second.rs");
+    fs::write(&first, "fn old_name() {}\n").unwrap();
+    fs::write(&second, "old_name();\nold_name();\n").unwrap();
+
+    let tool = SearchReplaceEditTool {
+        files: vec![
+            file_edits(&first, "old_name", "new_name"),
+            file_edits(&second, "old_name", "new_name"),
+            // The same file again sees the previous group's result.
+            file_edits(&second, "old_name", "new_name"),
+        ],
+        dry_run: false,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    let content_str = format!("{:?}", result.content[0]);
+    assert!(content_str.contains("3 edits to 2 files"));
+
+    assert_eq!(fs::read_to_string(&first).unwrap(), "fn new_name() {}\n");
+    assert_eq!(
+        fs::read_to_string(&second).unwrap(),
+        "new_name();\nnew_name();\n"
+    );
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_is_all_or_nothing() {
+    let temp_dir = TempDir::new().unwrap();
+    let paths: Vec<_> = (1..=3)
+        .map(|i| {
+            let path = temp_dir.path().join(format!("file{}.txt", i));
+            fs::write(&path, format!("value = {}\n", i)).unwrap();
+            path
+        })
+        .collect();
+
+    let tool = SearchReplaceEditTool {
+        files: vec![
+            file_edits(&paths[0], "value = 1", "value = 10"),
+            file_edits(&paths[1], "value = 2", "value = 20"),
+            file_edits(&paths[2], "missing text", "anything"),
+        ],
+        dry_run: false,
+        workspace: Workspace::default(),
+    };
+
+    let error = tool.call_tool().await.unwrap_err().to_string();
+    assert!(error.contains("file3.txt: Edit 1 failed"));
+    assert!(error.contains("No files were changed"));
+
+    for (i, path) in paths.iter().enumerate() {
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            format!("value = {}\n", i + 1)
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_dry_run() {
+    let temp_dir = TempDir::new().unwrap();
+    let first = temp_dir.path().join("first.txt");
+    let second = temp_dir.path().join("second.txt");
+    fs::write(&first, "alpha\nbeta\n").unwrap();
+    fs::write(&second, "gamma\n").unwrap();
+
+    let tool = SearchReplaceEditTool {
+        files: vec![
+            file_edits(&first, "beta", "BETA"),
+            file_edits(&second, "gamma", "GAMMA\ndelta"),
+        ],
+        dry_run: true,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+    assert!(text.starts_with("Dry run"));
+    assert!(text.contains("first.txt (1 edits, +1 -1)"));
+    assert!(text.contains("second.txt (1 edits, +2 -1)"));
+    assert!(text.contains("-beta\n+BETA\n"));
+    assert!(text.contains("-gamma\n+GAMMA\n+delta\n"));
+
+    assert_eq!(fs::read_to_string(&first).unwrap(), "alpha\nbeta\n");
+    assert_eq!(fs::read_to_string(&second).unwrap(), "gamma\n");
+}
+
+#[tokio::test]
+async fn test_edit_diffs_name_files_relative_to_workspace() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().canonicalize().unwrap();
+    fs::create_dir(root.join("src")).unwrap();
+    let path = root.join("src").join("lib.rs");
+    fs::write(&path, "old\n").unwrap();
+
+    let diff_of = |workspace: Workspace| {
+        let path = path.clone();
+        async move {
+            let tool = SearchReplaceEditTool {
+                files: vec![file_edits(&path, "old", "new")],
+                dry_run: true,
+                workspace,
+            };
+            let result = tool.call_tool().await.unwrap();
+            result.content[0].as_text().unwrap().text.clone()
+        }
+    };
+
+    let text = diff_of(Workspace::new(&root, &[]).unwrap()).await;
+    assert!(
+        text.contains("--- a/src/lib.rs\n+++ b/src/lib.rs\n"),
+        "{}",
+        text
+    );
+
+    let text = diff_of(Workspace::default()).await;
+    assert!(!text.contains("a//"), "{}", text);
+    assert!(text.contains("src/lib.rs\n"), "{}", text);
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_reports_closest_candidate() {
+    let content = "fn main() {\n    let total = 1;\n    println!(\"{}\", total);\n}\n";
+    let error = apply_single_edit(
+        content,
+        text_edit(
+            "fn main() {\n    let totl = 1;\n    println!(\"{}\", total);",
+            "replacement",
+        ),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(error.contains("Closest candidates"), "{}", error);
+    assert!(error.contains("Lines 1-3 ("), "{}", error);
+    assert!(error.contains("     1   fn main() {"), "{}", error);
+    assert!(error.contains("     2 -     let totl = 1;"), "{}", error);
+    assert!(error.contains("       +     let total = 1;"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_reports_no_candidates() {
+    let error = apply_single_edit(
+        "alpha\nbeta\n",
+        text_edit("completely unrelated text here", "replacement"),
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        error.contains("no region of the file resembles it"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_lists_ambiguous_matches() {
+    let content = "if x {\n  done();\n}\nif y {\n    done();\n}\n  done();\n";
+    let error = apply_single_edit(content, text_edit("done();  ", "replacement"))
+        .await
+        .unwrap_err();
+    assert!(error.contains("found 3 potential matches"), "{}", error);
+    assert!(error.contains("at line 2, line 5, line 7"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_fuzzy_multiline_match() {
+    let result = apply_single_edit(
+        "fn main() {\n    if ready {\n        go();\n    }\n}\n",
+        text_edit(
+            "if  ready {\n    go();\n}",
+            "if ready {\n    go();\n    again();\n}",
+        ),
+    )
+    .await;
+
+    assert_eq!(
+        result.unwrap(),
+        "fn main() {\n    if ready {\n        go();\n        again();\n    }\n}\n"
+    );
+}
+
+async fn apply_single_edit(content: &str, edit: EditOperation) -> Result<String, String> {
+    let (content, _) = with_temp_file(content, |path| async move {
+        SearchReplaceEditTool {
+            files: vec![FileEdits {
+                path,
+                edits: vec![edit],
+            }],
+            dry_run: false,
+            workspace: Workspace::default(),
+        }
+        .call_tool()
+        .await
+    })
+    .await?;
+    Ok(content)
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_fuzzy_match_normalizes_each_line() {
+    // Whitespace differs on every line, but the line breaks still count.
+    let result = apply_single_edit(
+        "fn a() {\n\tlet  x = 1;\n\treturn x;\n}\n",
+        EditOperation {
+            search: "fn a() {\n    let x = 1;\n    return x;".to_string(),
+            replace: "fn a() {\n    return 1;".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(result, "fn a() {\n    return 1;\n}\n");
+
+    // Text that only matches once lines are joined is not a match.
+    let error = apply_single_edit(
+        "let x =\n1;\n",
+        EditOperation {
+            search: "let  x = 1;".to_string(),
+            replace: "let x = 2;".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap_err();
+    assert!(error.contains("not found"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_fuzzy_match_reindents_relative_to_search() {
+    let content = "impl A {\n        fn f() {\n            body();\n        }\n}\n";
+    // The search is indented by four spaces where the file uses eight; the
+    // replacement keeps its nesting relative to the search text.
+    let result = apply_single_edit(
+        content,
+        EditOperation {
+            search: "    fn f()  {\n        body();\n    }".to_string(),
+            replace: "    fn f() {\n        body();\n        more();\n    }".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        result,
+        "impl A {\n        fn f() {\n            body();\n            more();\n        }\n}\n"
+    );
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_replace_all() {
+    let edit = EditOperation {
+        search: "count".to_string(),
+        replace: "total".to_string(),
+        replace_all: true,
+        ..Default::default()
+    };
+    let result = apply_single_edit("let count = 0;\ncount += 1;\nprint(count);\n", edit).await;
+    assert_eq!(
+        result.unwrap(),
+        "let total = 0;\ntotal += 1;\nprint(total);\n"
+    );
+
+    // Fuzzy matches are replaced everywhere too, each keeping its indentation.
+    let edit = EditOperation {
+        search: "step( );".to_string(),
+        replace: "next();".to_string(),
+        replace_all: true,
+        ..Default::default()
+    };
+    let result = apply_single_edit("  step(  );\n    step(  );\n", edit).await;
+    assert_eq!(result.unwrap(), "  next();\n    next();\n");
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_overlapping_fuzzy_matches() {
+    let edit = |selection: EditOperation| EditOperation {
+        search: " a\n a".to_string(),
+        replace: "b".to_string(),
+        ..selection
+    };
+
+    let error = apply_single_edit("a\na\na", edit(EditOperation::default()))
+        .await
+        .unwrap_err();
+    assert!(error.contains("found 2 potential matches"), "{}", error);
+    assert!(error.contains("at lines 1-2, lines 2-3"), "{}", error);
+
+    let error = apply_single_edit(
+        "a\na\na",
+        edit(EditOperation {
+            replace_all: true,
+            ..Default::default()
+        }),
+    )
+    .await
+    .unwrap_err();
+    assert!(error.contains("overlap"), "{}", error);
+
+    let result = apply_single_edit(
+        "a\na\na",
+        edit(EditOperation {
+            occurrence: Some(2),
+            ..Default::default()
+        }),
+    )
+    .await;
+    assert_eq!(result.unwrap(), "a\nb");
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_occurrence() {
+    let content = "block();\nblock();\nblock();\n";
+    let edit = EditOperation {
+        search: "block();".to_string(),
+        replace: "third();".to_string(),
+        occurrence: Some(3),
+        ..Default::default()
+    };
+    assert_eq!(
+        apply_single_edit(content, edit).await.unwrap(),
+        "block();\nblock();\nthird();\n"
+    );
+
+    let edit = EditOperation {
+        search: "block();".to_string(),
+        replace: "fourth();".to_string(),
+        occurrence: Some(4),
+        ..Default::default()
+    };
+    let error = apply_single_edit(content, edit).await.unwrap_err();
+    assert!(
+        error.contains("only 3 matches were found, at line 1, line 2, line 3"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_anchor_line() {
+    let content = "if a {\n    retry();\n}\nif b {\n    retry();\n}\n";
+    let edit = EditOperation {
+        search: "retry();  ".to_string(),
+        replace: "give_up();".to_string(),
+        anchor_line: Some(6),
+        ..Default::default()
+    };
+    assert_eq!(
+        apply_single_edit(content, edit).await.unwrap(),
+        "if a {\n    retry();\n}\nif b {\n    give_up();\n}\n"
+    );
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_rejects_conflicting_selection() {
+    let edit = EditOperation {
+        search: "x".to_string(),
+        replace: "y".to_string(),
+        replace_all: true,
+        occurrence: Some(1),
+        ..Default::default()
+    };
+    let error = apply_single_edit("x\n", edit).await.unwrap_err();
+    assert!(error.contains("Use only one of"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_regex_captures() {
+    let edit = EditOperation {
+        search: r"fn (\w+)\((\w+): i32\)".to_string(),
+        replace: "fn ${1}_i64($2: i64)".to_string(),
+        mode: EditMode::Regex,
+        replace_all: true,
+        expected_count: Some(2),
+        ..Default::default()
+    };
+    let result = apply_single_edit("fn double(x: i32) {}\nfn half(y: i32) {}\n", edit).await;
+    assert_eq!(
+        result.unwrap(),
+        "fn double_i64(x: i64) {}\nfn half_i64(y: i64) {}\n"
+    );
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_regex_unknown_group() {
+    let edit = |replace: &str| EditOperation {
+        search: r"foo(?<digit>\d)".to_string(),
+        replace: replace.to_string(),
+        mode: EditMode::Regex,
+        ..Default::default()
+    };
+
+    let error = apply_single_edit("foo1\n", edit("$1bar"))
+        .await
+        .unwrap_err();
+    assert!(error.starts_with("Invalid argument"), "{}", error);
+    assert!(error.contains("group $1bar"), "{}", error);
+    assert!(error.contains("${1}"), "{}", error);
+
+    let error = apply_single_edit("foo1\n", edit("${2}")).await.unwrap_err();
+    assert!(error.contains("group ${2}"), "{}", error);
+
+    let result = apply_single_edit("foo1\n", edit("${1}bar $digit $$2")).await;
+    assert_eq!(result.unwrap(), "1bar 1 $2\n");
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_regex_flags() {
+    let content = "TODO: one\n  todo: two\nnot a todo: three\n";
+    let edit = EditOperation {
+        search: r"^\s*todo: (.*)$".to_string(),
+        replace: "DONE: $1".to_string(),
+        mode: EditMode::Regex,
+        multi_line: true,
+        case_insensitive: true,
+        replace_all: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        apply_single_edit(content, edit).await.unwrap(),
+        "DONE: one\nDONE: two\nnot a todo: three\n"
+    );
+}
+
+#[tokio::test]
+async fn test_search_replace_edit_expected_count_guard() {
+    let content = "a1 a2 a3\n";
+    let edit = EditOperation {
+        search: r"a\d".to_string(),
+        replace: "b".to_string(),
+        mode: EditMode::Regex,
+        replace_all: true,
+        expected_count: Some(2),
+        ..Default::default()
+    };
+    let error = apply_single_edit(content, edit).await.unwrap_err();
+    assert!(
+        error.contains("Expected 2 replacements but the edit would make 3"),
+        "{}",
+        error
+    );
+
+    let edit = EditOperation {
+        search: "(".to_string(),
+        replace: "b".to_string(),
+        mode: EditMode::Regex,
+        ..Default::default()
+    };
+    let error = apply_single_edit(content, edit).await.unwrap_err();
+    assert!(error.contains("Invalid regex"), "{}", error);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_search_replace_edit_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let script = temp_dir.path().join("run.sh");
+    fs::write(&script, "echo old\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    SearchReplaceEditTool {
+        files: vec![file_edits(&script, "old", "new")],
+        dry_run: false,
+        workspace: Workspace::default(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+
+    assert_eq!(fs::read_to_string(&script).unwrap(), "echo new\n");
+    let mode = fs::metadata(&script).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o755);
+}
+
+async fn edit_lines(content: &str, operations: Vec<LineOperation>) -> Result<String, String> {
+    let (content, _) = with_temp_file(content, |path| async move {
+        EditLinesTool {
+            path,
+            operations,
+            dry_run: false,
+            workspace: Workspace::default(),
+        }
+        .call_tool()
+        .await
+    })
+    .await?;
+    Ok(content)
+}
+
+#[tokio::test]
+async fn test_edit_lines_uses_original_line_numbers() {
+    let result = edit_lines(
+        "one\ntwo\nthree\nfour\nfive\n",
+        vec![
+            LineOperation::Delete {
+                start: 4,
+                end: 5,
+                expected_hash: None,
+            },
+            LineOperation::InsertAfter {
+                line: 0,
+                content: "zero\n".to_string(),
+            },
+            LineOperation::Replace {
+                start: 2,
+                end: 3,
+                content: "TWO\nTHREE\nTHREE AND A HALF".to_string(),
+                expected_hash: None,
+            },
+            LineOperation::InsertAfter {
+                line: 3,
+                content: "after three".to_string(),
+            },
+        ],
+    )
+    .await;
+
+    assert_eq!(
+        result.unwrap(),
+        "zero\none\nTWO\nTHREE\nTHREE AND A HALF\nafter three\n"
+    );
+}
+
+#[tokio::test]
+async fn test_edit_lines_checks_expected_hash() {
+    let content = "one\ntwo\nthree\n";
+    let replace = |hash: &str| {
+        vec![LineOperation::Replace {
+            start: 2,
+            end: 3,
+            content: "2\n3".to_string(),
+            expected_hash: Some(hash.to_string()),
+        }]
+    };
+
+    let result = edit_lines(content, replace("f3952ccd5acbc312")).await;
+    assert_eq!(result.unwrap(), "one\n2\n3\n");
+
+    let error = edit_lines(content, replace("0000000000000000"))
+        .await
+        .unwrap_err();
+    assert!(error.contains("lines 2-3 have changed"), "{}", error);
+    assert!(
+        error.contains("f3952ccd5acbc3122b2fdc39d122b73e55f403fcb49dc411de7da4b4e987c07f"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_edit_lines_hashes_lines_as_stored() {
+    let replace = |hash: &str| {
+        vec![LineOperation::Replace {
+            start: 2,
+            end: 3,
+            content: "2\n3".to_string(),
+            expected_hash: Some(hash.to_string()),
+        }]
+    };
+
+    // `sed -n '2,3p' | sha256sum` of a file without a final newline.
+    let result = edit_lines("one\ntwo\nthree", replace("43fc3d02ea6e854a")).await;
+    assert_eq!(result.unwrap(), "one\n2\n3");
+
+    // ...and of a file with CRLF line endings.
+    let result = edit_lines("one\r\ntwo\r\nthree\r\n", replace("7f041f2072a3281f")).await;
+    assert_eq!(result.unwrap(), "one\r\n2\r\n3\r\n");
+}
+
+#[tokio::test]
+async fn test_edit_lines_rejects_bad_ranges() {
+    let content = "one\ntwo\nthree\n";
+
+    let error = edit_lines(
+        content,
+        vec![
+            LineOperation::Delete {
+                start: 1,
+                end: 2,
+                expected_hash: None,
+            },
+            LineOperation::InsertAfter {
+                line: 1,
+                content: "inside".to_string(),
+            },
+        ],
+    )
+    .await
+    .unwrap_err();
+    assert!(error.contains("Operations 1 and 2 overlap"), "{}", error);
+
+    let error = edit_lines(
+        content,
+        vec![LineOperation::Delete {
+            start: 3,
+            end: 4,
+            expected_hash: None,
+        }],
+    )
+    .await
+    .unwrap_err();
+    assert!(error.contains("past the end of the file"), "{}", error);
+}
+
 #[tokio::test]
 async fn test_task_complete_tool() {
     let tool = TaskCompleteTool {
@@ -226,6 +1122,12 @@ async fn test_task_complete_tool() {
 async fn test_read_nonexistent_file() {
     let tool = ReadFileTool {
         path: "/nonexistent/file.txt".to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await;
@@ -236,6 +1138,7 @@ async fn test_read_nonexistent_file() {
 async fn test_list_nonexistent_directory() {
     let tool = ListDirectoryTool {
         path: "/nonexistent/directory".to_string(),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await;
@@ -250,6 +1153,7 @@ async fn test_copy_nonexistent_file() {
     let tool = CopyPathTool {
         source_path: "/nonexistent/file.txt".to_string(),
         destination_path: dest_file.to_string_lossy().to_string(),
+        workspace: Workspace::default(),
     };
 
     let result = tool.call_tool().await;
@@ -258,35 +1162,1545 @@ async fn test_copy_nonexistent_file() {
 
 #[tokio::test]
 async fn test_execute_failing_command() {
+    let tool = command_tool(Invocation::shell("nonexistent_command_xyz123"));
+
+    let result = tool.call_tool().await.unwrap();
+    assert_eq!(result.is_error, Some(true));
+
+    let outcome: CommandOutcome = result.into_typed().unwrap();
+    assert_ne!(outcome.exit_code, Some(0));
+    assert!(!outcome.stderr.is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_structured_outcome() {
+    let tool = command_tool(Invocation::shell("echo out; echo err >&2; exit 4"));
+
+    let result = tool.call_tool().await.unwrap();
+    assert_eq!(result.is_error, Some(true));
+    let text = result.content[0].as_text().unwrap().text.clone();
+    assert!(text.contains("Command failed with exit code 4"));
+
+    let outcome: CommandOutcome = result.into_typed().unwrap();
+    assert_eq!(outcome.exit_code, Some(4));
+    assert_eq!(outcome.signal, None);
+    assert_eq!(outcome.stdout, "out\n");
+    assert_eq!(outcome.stderr, "err\n");
+    assert!(!outcome.timed_out);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_truncates_large_output() {
+    let output_dir = TempDir::new().unwrap();
     let tool = ExecuteCommandTool {
-        command: "nonexistent_command_xyz123".to_string(),
+        max_output_bytes: 200,
+        output_dir: Some(output_dir.path().to_path_buf()),
+        ..command_tool(Invocation::shell("seq 1 10000"))
     };
 
-    let result = tool.call_tool().await;
-    assert!(result.is_err());
+    let result = tool.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+    let outcome: CommandOutcome = result.into_typed().unwrap();
+
+    assert!(outcome.stdout.starts_with("1\n2\n"));
+    assert!(outcome.stdout.ends_with("9999\n10000\n"));
+    assert!(outcome.stdout.contains("lines omitted"));
+    assert!(outcome.stdout.len() < 300);
+
+    let stdout_file = outcome
+        .files
+        .stdout_file
+        .expect("full output should be spilled");
+    assert!(text.contains(&stdout_file));
+    assert!(std::path::Path::new(&stdout_file).starts_with(output_dir.path()));
+    let full_output = fs::read_to_string(&stdout_file).unwrap();
+    assert_eq!(full_output.lines().count(), 10000);
+
+    // Every line is either shown or counted as omitted.
+    let (head, rest) = outcome.stdout.split_once("… ").unwrap();
+    let (omitted, tail) = rest.split_once(" lines omitted …\n").unwrap();
+    let shown = head.lines().count() + tail.lines().count();
+    assert_eq!(shown + omitted.parse::<usize>().unwrap(), 10000);
+
+    assert_eq!(outcome.files.stderr_file, None);
 }
 
+#[cfg(unix)]
 #[tokio::test]
-async fn test_apply_patch_tool() {
-    let temp_dir = TempDir::new().unwrap();
-    let file_path = temp_dir.path().join("patch_test.txt");
-    let original_content = "line1\nline2\nline3";
+async fn test_execute_command_spills_output_as_it_arrives() {
+    let output_dir = TempDir::new().unwrap();
+    let tool = |command: &str| ExecuteCommandTool {
+        max_output_bytes: 1000,
+        output_dir: Some(output_dir.path().to_path_buf()),
+        ..command_tool(Invocation::shell(command))
+    };
 
-    fs::write(&file_path, original_content).unwrap();
+    let outcome: CommandOutcome = tool("yes | head -n 1000000")
+        .call_tool()
+        .await
+        .unwrap()
+        .into_typed()
+        .unwrap();
+    assert!(outcome.stdout.len() < 1100);
+    let stdout_file = outcome
+        .files
+        .stdout_file
+        .expect("full output should be spilled");
+    assert_eq!(fs::metadata(&stdout_file).unwrap().len(), 2_000_000);
+
+    // Output that fits leaves no file behind.
+    tool("echo short").call_tool().await.unwrap();
+    assert_eq!(fs::read_dir(output_dir.path()).unwrap().count(), 1);
+}
 
-    let patch = "@@ -1,3 +1,3 @@\n line1\n-line2\n+modified line2\n line3";
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_environment_and_stdin() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("subproject")).unwrap();
 
-    let tool = ApplyPatchTool {
-        path: file_path.to_string_lossy().to_string(),
-        patch: patch.to_string(),
+    let tool = ExecuteCommandTool {
+        working_directory: Some("subproject".to_string()),
+        env: HashMap::from([("GREETING".to_string(), "hello env".to_string())]),
+        stdin: Some("piped input".to_string()),
+        workspace: Workspace::new(temp_dir.path(), &[]).unwrap(),
+        ..command_tool(Invocation::shell("pwd; echo \"$GREETING\"; cat"))
     };
 
     let result = tool.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
 
-    assert_eq!(result.content.len(), 1);
-    let content_str = format!("{:?}", result.content[0]);
-    assert!(content_str.contains("Successfully applied patch"));
+    assert!(text.contains("subproject"));
+    assert!(text.contains("hello env"));
+    assert!(text.contains("piped input"));
+}
 
-    let patched_content = fs::read_to_string(&file_path).unwrap();
-    assert!(patched_content.contains("modified line2"));
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_env_clear() {
+    std::env::set_var("COMMON_TOOLS_TEST_INHERITED", "leaked");
+
+    let tool = ExecuteCommandTool {
+        env: HashMap::from([("ONLY_VAR".to_string(), "kept".to_string())]),
+        env_clear: true,
+        ..command_tool(Invocation::shell(
+            "echo \"[$COMMON_TOOLS_TEST_INHERITED][$ONLY_VAR]\"",
+        ))
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+
+    assert!(text.contains("[][kept]"));
+}
+
+#[tokio::test]
+async fn test_execute_command_working_directory_outside_workspace() {
+    let root = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+
+    let tool = ExecuteCommandTool {
+        working_directory: Some(outside.path().to_string_lossy().to_string()),
+        workspace: Workspace::new(root.path(), &[]).unwrap(),
+        ..command_tool(Invocation::shell("echo hi"))
+    };
+
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::OutsideWorkspace(_))));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_timeout_kills_process_group() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("survived");
+
+    let tool = ExecuteCommandTool {
+        timeout_ms: Some(300),
+        ..command_tool(Invocation::shell(format!(
+            "(sleep 1; touch {}) & echo started; sleep 30",
+            marker.display()
+        )))
+    };
+
+    let started = std::time::Instant::now();
+    let result = tool.call_tool().await.unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(result.is_error, Some(true));
+
+    let message = result.content[0].as_text().unwrap().text.clone();
+    assert!(message.contains("timed out after 300 ms"));
+    assert!(message.contains("started"));
+
+    let outcome: CommandOutcome = result.into_typed().unwrap();
+    assert!(outcome.timed_out);
+    assert_eq!(outcome.signal, Some(9));
+
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert!(!marker.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_cancellation() {
+    let cancellation_token = CancellationToken::new();
+    let tool = ExecuteCommandTool {
+        cancellation_token: cancellation_token.clone(),
+        ..command_tool(Invocation::shell("echo waiting; sleep 30"))
+    };
+
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        cancellation_token.cancel();
+    });
+
+    let result = tool.call_tool().await.unwrap();
+    assert_eq!(result.is_error, Some(true));
+
+    let message = result.content[0].as_text().unwrap().text.clone();
+    assert!(message.contains("cancelled"));
+    assert!(message.contains("waiting"));
+}
+
+#[tokio::test]
+async fn test_apply_patch_tool() {
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("patch_test.txt");
+    let original_content = "line1\nline2\nline3";
+
+    fs::write(&file_path, original_content).unwrap();
+
+    let patch = "@@ -1,3 +1,3 @@\n line1\n-line2\n+modified line2\n line3";
+
+    let tool = ApplyPatchTool {
+        path: Some(file_path.to_string_lossy().to_string()),
+        patch: patch.to_string(),
+        directory: None,
+        strip: None,
+        dry_run: false,
+        fuzz: DEFAULT_FUZZ,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+
+    assert_eq!(result.content.len(), 1);
+    let content_str = format!("{:?}", result.content[0]);
+    assert!(content_str.contains("Successfully applied patch"));
+
+    assert!(content_str.contains("+modified line2"));
+
+    let patched_content = fs::read_to_string(&file_path).unwrap();
+    assert!(patched_content.contains("modified line2"));
+}
+
+#[tokio::test]
+async fn test_apply_patch_dry_run() {
+    let temp_dir = TempDir::new().unwrap();
+    let file_path = temp_dir.path().join("patch_test.txt");
+    fs::write(&file_path, "line1\nline2\nline3\n").unwrap();
+
+    let tool = ApplyPatchTool {
+        path: Some(file_path.to_string_lossy().to_string()),
+        patch: "@@ -1,3 +1,3 @@\n line1\n-line2\n+modified line2\n line3".to_string(),
+        directory: None,
+        strip: None,
+        dry_run: true,
+        fuzz: DEFAULT_FUZZ,
+        workspace: Workspace::default(),
+    };
+
+    let result = tool.call_tool().await.unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+    assert!(text.starts_with("Dry run"));
+    assert!(text.contains("(+1 -1)"));
+    assert!(text.contains("-line2\n+modified line2\n"));
+    assert_eq!(
+        fs::read_to_string(&file_path).unwrap(),
+        "line1\nline2\nline3\n"
+    );
+}
+
+async fn apply_patch(content: &str, patch: &str, fuzz: usize) -> Result<(String, String), String> {
+    let (content, result) = with_temp_file(content, |path| async move {
+        ApplyPatchTool {
+            path: Some(path),
+            patch: patch.to_string(),
+            directory: None,
+            strip: None,
+            dry_run: false,
+            fuzz,
+            workspace: Workspace::default(),
+        }
+        .call_tool()
+        .await
+    })
+    .await?;
+    Ok((content, result.content[0].as_text().unwrap().text.clone()))
+}
+
+#[tokio::test]
+async fn test_apply_patch_finds_hunks_at_an_offset() {
+    let content = "new1\nnew2\nnew3\na\nb\nc\nd\ne\nf\ng\nh\n";
+    // Written against the file before three lines were added at the top; the
+    // second hunk is found by carrying over the first one's offset.
+    let patch = "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n@@ -6,3 +6,3 @@\n f\n-g\n+G\n h\n";
+
+    let (result, message) = apply_patch(content, patch, 0).await.unwrap();
+    assert_eq!(result, "new1\nnew2\nnew3\na\nB\nc\nd\ne\nf\nG\nh\n");
+    assert!(
+        message.contains("Hunk #1 applied at line 4 (offset +3 lines, fuzz 0)"),
+        "{}",
+        message
+    );
+    assert!(
+        message.contains("Hunk #2 applied at line 9 (offset +3 lines, fuzz 0)"),
+        "{}",
+        message
+    );
+
+    // Hunks also move up, choosing the match nearest the stated line.
+    let content = "x\nb\ny\nb\nz\n";
+    let (result, message) = apply_patch(content, "@@ -9,1 +9,1 @@\n-b\n+B\n", 0)
+        .await
+        .unwrap();
+    assert_eq!(result, "x\nb\ny\nB\nz\n");
+    assert!(message.contains("offset -5 lines"), "{}", message);
+}
+
+#[tokio::test]
+async fn test_apply_patch_fuzz_ignores_outer_context() {
+    let content = "one\ntwo\nthree\nfour\nfive\n";
+    // The outer context lines no longer match the file.
+    let patch = "@@ -1,5 +1,5 @@\n ONE\n two\n-three\n+3\n four\n FIVE\n";
+
+    let error = apply_patch(content, patch, 0).await.unwrap_err();
+    assert!(
+        error.contains("Hunk #1 did not match near line 1"),
+        "{}",
+        error
+    );
+    assert!(error.contains("nothing was changed"), "{}", error);
+
+    let (result, message) = apply_patch(content, patch, 1).await.unwrap();
+    assert_eq!(result, "one\ntwo\n3\nfour\nfive\n");
+    assert!(
+        message.contains("Hunk #1 applied at line 1 (offset +0 lines, fuzz 1)"),
+        "{}",
+        message
+    );
+
+    // Fuzz never drops the lines being removed.
+    let error = apply_patch(content, "@@ -1,3 +1,3 @@\n one\n-2\n+TWO\n three\n", 2)
+        .await
+        .unwrap_err();
+    assert!(error.contains("Hunk #1 did not match"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_apply_patch_no_newline_at_end_of_file() {
+    let adds = "@@ -1,2 +1,2 @@\n one\n-two\n\\ No newline at end of file\n+two\n";
+    let (patched, message) = apply_patch("one\ntwo", adds, 0).await.unwrap();
+    assert_eq!(patched, "one\ntwo\n");
+    assert!(message.contains("+1 -1"), "{}", message);
+
+    let removes = "@@ -1,2 +1,2 @@\n one\n-two\n+two\n\\ No newline at end of file\n";
+    let (patched, _) = apply_patch("one\ntwo\n", removes, 0).await.unwrap();
+    assert_eq!(patched, "one\ntwo");
+
+    // The old side has to match too.
+    let error = apply_patch("one\ntwo\n", adds, 0).await.unwrap_err();
+    assert!(error.contains("end without a newline"), "{}", error);
+
+    // Git diffs carry the marker the same way.
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("notes.txt"), "one\ntwo").unwrap();
+    let patch = "diff --git a/notes.txt b/notes.txt\n--- a/notes.txt\n+++ b/notes.txt\n\
+                 @@ -1,2 +1,3 @@\n one\n-two\n\\ No newline at end of file\n+two\n+three\n";
+    apply_patch_in(temp_dir.path(), patch, None).await.unwrap();
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("notes.txt")).unwrap(),
+        "one\ntwo\nthree\n"
+    );
+}
+
+#[tokio::test]
+async fn test_apply_patch_reads_hunks_by_their_header_counts() {
+    let content = "one\ntwo\nthree\n";
+    // git format-patch ends with a signature that looks like a removed line.
+    let patch = "@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n-- \n2.43.0\n";
+    let (result, _) = apply_patch(content, patch, 0).await.unwrap();
+    assert_eq!(result, "one\n2\nthree\n");
+
+    let error = apply_patch(content, "@@ -1,2 +1,3 @@\n one\n-two\n+2\n three\n", 0)
+        .await
+        .unwrap_err();
+    assert!(
+        error.contains("doesn't match its header's line counts"),
+        "{}",
+        error
+    );
+
+    let error = apply_patch(content, "@@ -1,4 +1,4 @@\n one\n-two\n+2\n three\n", 0)
+        .await
+        .unwrap_err();
+    assert!(
+        error.contains("The patch ends before the hunk"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_apply_patch_reports_every_failed_hunk() {
+    let content = "a\nb\nc\nd\ne\nf\n";
+    let patch =
+        "@@ -1,2 +1,2 @@\n-x\n+X\n b\n@@ -4,2 +4,2 @@\n-d\n+D\n e\n@@ -5,2 +5,2 @@\n-y\n+Y\n f\n";
+
+    let error = apply_patch(content, patch, DEFAULT_FUZZ).await.unwrap_err();
+    assert!(error.contains("Hunk #1 did not match"), "{}", error);
+    assert!(!error.contains("Hunk #2"), "{}", error);
+    assert!(error.contains("Hunk #3 did not match"), "{}", error);
+}
+
+async fn apply_patch_in(
+    directory: &std::path::Path,
+    patch: &str,
+    strip: Option<usize>,
+) -> Result<String, String> {
+    let tool = ApplyPatchTool {
+        path: None,
+        patch: patch.to_string(),
+        directory: Some(directory.to_string_lossy().to_string()),
+        strip,
+        dry_run: false,
+        fuzz: DEFAULT_FUZZ,
+        workspace: Workspace::default(),
+    };
+    match tool.call_tool().await {
+        Ok(result) => Ok(result.content[0].as_text().unwrap().text.clone()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_apply_patch_multiple_files_git_style() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::create_dir(root.join("src")).unwrap();
+    fs::write(root.join("src/lib.rs"), "mod a;\nmod b;\n").unwrap();
+    fs::write(root.join("src/old.rs"), "fn old() {}\n").unwrap();
+    fs::write(root.join("gone.txt"), "bye\nnow\n").unwrap();
+    fs::write(root.join("run.sh"), "echo hi\n").unwrap();
+
+    let patch = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,2 +1,3 @@
+ mod a;
+ mod b;
++mod c;
+diff --git a/src/c.rs b/src/c.rs
+new file mode 100644
+index 0000000..3333333
+--- /dev/null
++++ b/src/c.rs
+@@ -0,0 +1,2 @@
++// New module.
++fn c() {}
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+index 4444444..0000000
+--- a/gone.txt
++++ /dev/null
+@@ -1,2 +0,0 @@
+-bye
+-now
+diff --git a/src/old.rs b/src/new.rs
+similarity index 80%
+rename from src/old.rs
+rename to src/new.rs
+index 5555555..6666666 100644
+--- a/src/old.rs
++++ b/src/new.rs
+@@ -1 +1 @@
+-fn old() {}
++fn new() {}
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+";
+
+    let message = apply_patch_in(root, patch, None).await.unwrap();
+    assert!(message.contains("5 files:"), "{}", message);
+    assert!(message.contains("c.rs (created, +2 -0)"), "{}", message);
+    assert!(message.contains("gone.txt (deleted, +0 -2)"), "{}", message);
+    assert!(message.contains("new.rs (renamed from "), "{}", message);
+    assert!(message.contains("run.sh (mode 755, +0 -0)"), "{}", message);
+
+    assert_eq!(
+        fs::read_to_string(root.join("src/lib.rs")).unwrap(),
+        "mod a;\nmod b;\nmod c;\n"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("src/c.rs")).unwrap(),
+        "// New module.\nfn c() {}\n"
+    );
+    assert!(!root.join("gone.txt").exists());
+    assert!(!root.join("src/old.rs").exists());
+    assert_eq!(
+        fs::read_to_string(root.join("src/new.rs")).unwrap(),
+        "fn new() {}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("run.sh")).unwrap(),
+        "echo hi\n"
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(root.join("run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+}
+
+#[tokio::test]
+async fn test_apply_patch_multiple_files_is_atomic() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::write(root.join("first.txt"), "one\ntwo\n").unwrap();
+    fs::write(root.join("second.txt"), "three\nfour\n").unwrap();
+
+    let patch = "\
+--- a/first.txt
++++ b/first.txt
+@@ -1,2 +1,2 @@
+-one
++ONE
+ two
+--- a/second.txt
++++ b/second.txt
+@@ -1,2 +1,2 @@
+-missing
++MISSING
+ four
+--- /dev/null
++++ b/third.txt
+@@ -0,0 +1 @@
++three
+";
+
+    let error = apply_patch_in(root, patch, None).await.unwrap_err();
+    assert!(error.contains("nothing was changed"), "{}", error);
+    assert!(
+        error.contains("second.txt: Hunk #1 did not match"),
+        "{}",
+        error
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("first.txt")).unwrap(),
+        "one\ntwo\n"
+    );
+    assert!(!root.join("third.txt").exists());
+
+    // Creating a file that already exists is refused up front.
+    let error = apply_patch_in(
+        root,
+        "--- /dev/null\n+++ b/first.txt\n@@ -0,0 +1 @@\n+x\n",
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert!(error.contains("already exists"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_apply_patch_changed_lines_that_look_like_file_headers() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::write(root.join("notes.md"), "intro\n-- a\nend\n").unwrap();
+
+    // Removing "-- a" and adding "++ b" reads like a ---/+++ file header.
+    let patch = "\
+--- a/notes.md
++++ b/notes.md
+@@ -1,3 +1,3 @@
+ intro
+--- a
++++ b
+ end
+";
+    let message = apply_patch_in(root, patch, None).await.unwrap();
+    assert!(message.contains("notes.md (+1 -1)"), "{}", message);
+    assert_eq!(
+        fs::read_to_string(root.join("notes.md")).unwrap(),
+        "intro\n++ b\nend\n"
+    );
+}
+
+#[tokio::test]
+async fn test_apply_patch_strip_level() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::create_dir(root.join("docs")).unwrap();
+    fs::write(root.join("docs/notes.txt"), "draft\n").unwrap();
+
+    let patch = "--- project.orig/docs/notes.txt\n+++ project/docs/notes.txt\n@@ -1 +1 @@\n-draft\n+final\n";
+
+    // Without -p1 the paths are taken as they are and don't exist.
+    let error = apply_patch_in(root, patch, None).await.unwrap_err();
+    assert!(error.contains("notes.txt"), "{}", error);
+
+    apply_patch_in(root, patch, Some(1)).await.unwrap();
+    assert_eq!(
+        fs::read_to_string(root.join("docs/notes.txt")).unwrap(),
+        "final\n"
+    );
+
+    let error = apply_patch_in(root, patch, Some(5)).await.unwrap_err();
+    assert!(
+        error.contains("fewer than 5 leading directories"),
+        "{}",
+        error
+    );
+}
+
+const WINDOWS_FILE: &str = "\u{feff}first\r\nsecond\r\nthird\r\n";
+
+#[tokio::test]
+async fn test_editing_tools_preserve_line_endings_and_bom() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("windows.txt");
+    let path_str = path.to_string_lossy().to_string();
+
+    fs::write(&path, WINDOWS_FILE).unwrap();
+    let result = SearchReplaceEditTool {
+        files: vec![file_edits(&path, "second", "2nd")],
+        dry_run: false,
+        workspace: Workspace::default(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+    assert!(text.contains("(+1 -1)"), "{}", text);
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "\u{feff}first\r\n2nd\r\nthird\r\n"
+    );
+
+    fs::write(&path, WINDOWS_FILE).unwrap();
+    ApplyPatchTool {
+        path: Some(path_str.clone()),
+        patch: "@@ -1,3 +1,3 @@\n first\n-second\n+2nd\n third".to_string(),
+        directory: None,
+        strip: None,
+        dry_run: false,
+        fuzz: DEFAULT_FUZZ,
+        workspace: Workspace::default(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "\u{feff}first\r\n2nd\r\nthird\r\n"
+    );
+
+    fs::write(&path, WINDOWS_FILE).unwrap();
+    EditLinesTool {
+        path: path_str.clone(),
+        operations: vec![LineOperation::InsertAfter {
+            line: 3,
+            content: "fourth\n".to_string(),
+        }],
+        dry_run: false,
+        workspace: Workspace::default(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "\u{feff}first\r\nsecond\r\nthird\r\nfourth\r\n"
+    );
+
+    fs::write(&path, WINDOWS_FILE).unwrap();
+    WriteFileTool {
+        path: path_str,
+        content: "one\ntwo".to_string(),
+        workspace: Workspace::default(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "\u{feff}one\r\ntwo\r\n");
+}
+
+#[tokio::test]
+async fn test_editing_tools_keep_missing_final_newline_and_mixed_endings() {
+    // Mixed line endings are left exactly as they were outside the edit.
+    let result = apply_single_edit(
+        "alpha\r\nbeta\ngamma",
+        EditOperation {
+            search: "beta".to_string(),
+            replace: "BETA".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(result, "alpha\r\nBETA\ngamma");
+
+    let result = edit_lines(
+        "one\r\ntwo\r\nthree",
+        vec![LineOperation::Delete {
+            start: 2,
+            end: 2,
+            expected_hash: None,
+        }],
+    )
+    .await
+    .unwrap();
+    assert_eq!(result, "one\r\nthree");
+}
+
+#[tokio::test]
+async fn test_workspace_rejects_absolute_path_outside_root() {
+    let root = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    let outside_file = outside.path().join("secret.txt");
+    fs::write(&outside_file, "secret").unwrap();
+
+    let tool = ReadFileTool {
+        path: outside_file.to_string_lossy().to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: Workspace::new(root.path(), &[]).unwrap(),
+    };
+
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::OutsideWorkspace(_))));
+}
+
+#[tokio::test]
+async fn test_workspace_rejects_parent_traversal() {
+    let root = TempDir::new().unwrap();
+
+    let tool = WriteFileTool {
+        path: "../escaped.txt".to_string(),
+        content: "nope".to_string(),
+        workspace: Workspace::new(root.path(), &[]).unwrap(),
+    };
+
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::OutsideWorkspace(_))));
+    assert!(!root.path().parent().unwrap().join("escaped.txt").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_workspace_rejects_symlink_escape() {
+    let root = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    std::os::unix::fs::symlink(outside.path(), root.path().join("link")).unwrap();
+
+    let tool = WriteFileTool {
+        path: "link/escaped.txt".to_string(),
+        content: "nope".to_string(),
+        workspace: Workspace::new(root.path(), &[]).unwrap(),
+    };
+
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::OutsideWorkspace(_))));
+    assert!(!outside.path().join("escaped.txt").exists());
+}
+
+#[tokio::test]
+async fn test_workspace_resolves_relative_paths_against_root() {
+    let root = TempDir::new().unwrap();
+
+    let tool = WriteFileTool {
+        path: "nested/file.txt".to_string(),
+        content: "inside".to_string(),
+        workspace: Workspace::new(root.path(), &[]).unwrap(),
+    };
+
+    tool.call_tool().await.unwrap();
+
+    let written = fs::read_to_string(root.path().join("nested/file.txt")).unwrap();
+    assert_eq!(written, "inside");
+}
+
+#[tokio::test]
+async fn test_workspace_read_only_roots() {
+    let root = TempDir::new().unwrap();
+    let reference = TempDir::new().unwrap();
+    let reference_file = reference.path().join("docs.txt");
+    fs::write(&reference_file, "reference docs").unwrap();
+
+    let workspace = Workspace::new(root.path(), &[reference.path().to_path_buf()]).unwrap();
+
+    let read = ReadFileTool {
+        path: reference_file.to_string_lossy().to_string(),
+        offset: 1,
+        limit: DEFAULT_LINE_LIMIT,
+        max_bytes: DEFAULT_MAX_BYTES,
+        encoding: None,
+        max_image_dimension: None,
+        workspace: workspace.clone(),
+    };
+    let result = read.call_tool().await.unwrap();
+    let content_str = format!("{:?}", result.content[0]);
+    assert!(content_str.contains("reference docs"));
+
+    let write = WriteFileTool {
+        path: reference_file.to_string_lossy().to_string(),
+        content: "overwritten".to_string(),
+        workspace,
+    };
+    let result = write.call_tool().await;
+    assert!(matches!(result, Err(ToolError::OutsideWorkspace(_))));
+    assert_eq!(
+        fs::read_to_string(&reference_file).unwrap(),
+        "reference docs"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_workspace_moves_symlinks_not_their_targets() {
+    let root = TempDir::new().unwrap();
+    fs::write(root.path().join("real.txt"), "real").unwrap();
+    std::os::unix::fs::symlink("real.txt", root.path().join("link.txt")).unwrap();
+
+    let tool = MovePathTool {
+        source_path: "link.txt".to_string(),
+        destination_path: "moved.txt".to_string(),
+        workspace: Workspace::new(root.path(), &[]).unwrap(),
+    };
+    tool.call_tool().await.unwrap();
+
+    assert_eq!(
+        fs::read_to_string(root.path().join("real.txt")).unwrap(),
+        "real"
+    );
+    assert!(fs::symlink_metadata(root.path().join("link.txt")).is_err());
+    let moved = fs::symlink_metadata(root.path().join("moved.txt")).unwrap();
+    assert!(moved.file_type().is_symlink());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_workspace_checks_copied_directory_destinations() {
+    let root = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    fs::create_dir_all(root.path().join("src")).unwrap();
+    fs::write(root.path().join("src/escaped.txt"), "copied").unwrap();
+    // An existing link inside the destination tree points out of the root.
+    fs::create_dir_all(root.path().join("dst")).unwrap();
+    std::os::unix::fs::symlink(
+        outside.path().join("escaped.txt"),
+        root.path().join("dst/escaped.txt"),
+    )
+    .unwrap();
+
+    let tool = CopyPathTool {
+        source_path: "src".to_string(),
+        destination_path: "dst".to_string(),
+        workspace: Workspace::new(root.path(), &[]).unwrap(),
+    };
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::OutsideWorkspace(_))));
+    assert!(!outside.path().join("escaped.txt").exists());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_copy_directory_keeps_non_utf8_names() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let name = OsStr::from_bytes(b"caf\xe9.txt");
+    fs::create_dir(temp_dir.path().join("src")).unwrap();
+    fs::write(temp_dir.path().join("src").join(name), "latin1").unwrap();
+
+    let tool = CopyPathTool {
+        source_path: temp_dir.path().join("src").to_string_lossy().to_string(),
+        destination_path: temp_dir.path().join("dst").to_string_lossy().to_string(),
+        workspace: Workspace::default(),
+    };
+    tool.call_tool().await.unwrap();
+
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("dst").join(name)).unwrap(),
+        "latin1"
+    );
+}
+
+#[cfg(unix)]
+fn background_command(command: &str, processes: &ProcessRegistry) -> ExecuteCommandTool {
+    ExecuteCommandTool {
+        background: true,
+        processes: processes.clone(),
+        ..command_tool(Invocation::shell(command))
+    }
+}
+
+#[cfg(unix)]
+async fn poll_output_until(processes: &ProcessRegistry, process_id: u32, needle: &str) -> String {
+    let mut collected = String::new();
+    for _ in 0..50 {
+        let result = ProcessOutputTool {
+            process_id,
+            processes: processes.clone(),
+        }
+        .call_tool()
+        .await
+        .unwrap();
+        collected.push_str(&result.content[0].as_text().unwrap().text);
+        if collected.contains(needle) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    collected
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_background_process_lifecycle() {
+    let processes = ProcessRegistry::new();
+
+    let result = background_command("echo ready; cat", &processes)
+        .call_tool()
+        .await
+        .unwrap();
+    let text = result.content[0].as_text().unwrap().text.clone();
+    assert!(text.contains("Started background process 1"));
+
+    let output = poll_output_until(&processes, 1, "ready").await;
+    assert!(output.contains("is running"));
+    assert!(output.contains("ready"));
+
+    ProcessWriteStdinTool {
+        process_id: 1,
+        input: "ping\n".to_string(),
+        close_stdin: false,
+        processes: processes.clone(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+
+    let output = poll_output_until(&processes, 1, "ping").await;
+    assert!(output.contains("ping"));
+    assert!(!output.contains("ready"));
+
+    let list = ProcessListTool {
+        processes: processes.clone(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+    let text = list.content[0].as_text().unwrap().text.clone();
+    assert!(text.contains("[1]"));
+    assert!(text.contains("running"));
+    assert!(text.contains("echo ready; cat"));
+
+    let killed = ProcessKillTool {
+        process_id: 1,
+        processes: processes.clone(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+    let text = killed.content[0].as_text().unwrap().text.clone();
+    assert!(text.contains("Killed process 1"));
+
+    assert!(processes.list().is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_background_process_reports_exit() {
+    let processes = ProcessRegistry::new();
+
+    background_command("echo done; exit 3", &processes)
+        .call_tool()
+        .await
+        .unwrap();
+
+    let output = poll_output_until(&processes, 1, "exited with code 3").await;
+    assert!(output.contains("done"));
+    assert!(output.contains("exited with code 3"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_background_process_output_is_capped() {
+    let processes = ProcessRegistry::new();
+    let mut tool = background_command("seq 1 10000; echo finished", &processes);
+    tool.max_output_bytes = 100;
+    tool.call_tool().await.unwrap();
+
+    let output = poll_output_until(&processes, 1, "exited with code 0").await;
+    assert!(output.contains("earlier bytes dropped"));
+    assert!(output.contains("finished"));
+    assert!(!output.contains("\n1\n"));
+    assert!(output.len() < 1000);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_background_processes_killed_when_registry_dropped() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("survived");
+    let processes = ProcessRegistry::new();
+
+    background_command(&format!("sleep 1; touch {}", marker.display()), &processes)
+        .call_tool()
+        .await
+        .unwrap();
+
+    drop(processes);
+
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert!(!marker.exists());
+}
+
+#[cfg(unix)]
+fn policy_command(command: &str, policy: &str) -> ExecuteCommandTool {
+    ExecuteCommandTool {
+        policy: CommandPolicy::from_toml(policy).unwrap(),
+        ..command_tool(Invocation::shell(command))
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_denied_by_policy() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("marker");
+    let policy = "[[rules]]\naction = \"deny\"\nprogram = \"touch\"\nreason = \"no touching\"";
+
+    let result = policy_command(&format!("echo hi; touch {}", marker.display()), policy)
+        .call_tool()
+        .await;
+
+    let Err(ToolError::PolicyViolation(message)) = result else {
+        panic!("expected a policy violation, got {:?}", result);
+    };
+    assert!(message.contains("no touching"));
+    assert!(!marker.exists());
+
+    let result = policy_command("echo allowed", policy).call_tool().await;
+    assert_eq!(result.unwrap().is_error, Some(false));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_confirmation_requires_client() {
+    let policy = "[[rules]]\naction = \"confirm\"\nprogram = \"echo\"";
+
+    for background in [false, true] {
+        let mut tool = policy_command("echo needs approval", policy);
+        tool.background = background;
+
+        let result = tool.call_tool().await;
+        let Err(ToolError::PolicyViolation(message)) = result else {
+            panic!("expected a policy violation, got {:?}", result);
+        };
+        assert!(message.contains("requires confirmation"));
+        assert!(tool.processes.list().is_empty());
+    }
+}
+
+#[cfg(unix)]
+fn direct_command(program: &str, args: &[&str]) -> ExecuteCommandTool {
+    command_tool(Invocation::Direct {
+        program: program.to_string(),
+        args: args.iter().map(|a| a.to_string()).collect(),
+    })
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_direct_program_skips_shell() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("injected");
+    let tricky = format!("$HOME; touch {} && echo \"quoted\"", marker.display());
+
+    let result = direct_command("printf", &["%s|", &tricky, "two words"])
+        .call_tool()
+        .await
+        .unwrap();
+
+    let outcome: CommandOutcome =
+        serde_json::from_value(result.structured_content.unwrap()).unwrap();
+    assert_eq!(outcome.stdout, format!("{}|two words|", tricky));
+    assert!(!marker.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_direct_program_not_found() {
+    let result = direct_command("nonexistent_program_xyz123", &[])
+        .call_tool()
+        .await;
+
+    let Err(ToolError::CommandFailed(message)) = result else {
+        panic!("expected a spawn failure, got {:?}", result);
+    };
+    assert!(message.contains("nonexistent_program_xyz123"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_selects_shell() {
+    if std::process::Command::new("bash")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        return;
+    }
+
+    let tool = command_tool(Invocation::Shell {
+        command: "echo \"${BASH_VERSION:+bash}\"; [[ 1 == 1 ]] && echo brackets".to_string(),
+        shell: Some(Shell::Bash),
+    });
+
+    let result = tool.call_tool().await.unwrap();
+    let outcome: CommandOutcome =
+        serde_json::from_value(result.structured_content.unwrap()).unwrap();
+    assert_eq!(outcome.stdout, "bash\nbrackets\n");
+}
+
+#[test]
+fn test_invocation_requires_exactly_one_form() {
+    let invocation = Invocation::new(
+        None,
+        Some("git".to_string()),
+        Some(vec![
+            "commit".to_string(),
+            "-m".to_string(),
+            "it's done".to_string(),
+        ]),
+        None,
+    )
+    .unwrap();
+    assert_eq!(invocation.to_string(), r#"git commit -m 'it'\''s done'"#);
+
+    for (command, program, args, shell) in [
+        (Some("ls"), Some("ls"), None, None),
+        (None, None, None, None),
+        (Some("ls"), None, Some(vec!["-l".to_string()]), None),
+        (None, Some("ls"), None, Some(Shell::Bash)),
+    ] {
+        let result = Invocation::new(
+            command.map(String::from),
+            program.map(String::from),
+            args,
+            shell,
+        );
+        assert!(matches!(result, Err(ToolError::InvalidArgument(_))));
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_policy_checks_direct_programs() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("marker");
+    let marker = marker.to_string_lossy();
+
+    let mut tool = direct_command("bash", &["-c", &format!("touch {}", marker)]);
+    tool.policy =
+        CommandPolicy::from_toml("[[rules]]\naction = \"deny\"\nprogram = \"touch\"").unwrap();
+
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::PolicyViolation(_))));
+    assert!(!temp_dir.path().join("marker").exists());
+}
+
+#[cfg(unix)]
+fn pty_command(command: &str, size: PtySize) -> ExecuteCommandTool {
+    ExecuteCommandTool {
+        pty: Some(size),
+        ..command_tool(Invocation::shell(command))
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_pty_mode() {
+    let tool = pty_command(
+        "[ -t 0 ] && [ -t 1 ] && echo is-a-tty; stty size; \
+         printf '\\033[31mred\\033[0m\\n'; echo err >&2",
+        PtySize {
+            rows: 30,
+            cols: 100,
+        },
+    );
+
+    let result = tool.call_tool().await.unwrap();
+    let outcome: CommandOutcome =
+        serde_json::from_value(result.structured_content.unwrap()).unwrap();
+    assert_eq!(outcome.exit_code, Some(0));
+    assert_eq!(outcome.stdout, "is-a-tty\n30 100\nred\nerr\n");
+    assert_eq!(outcome.stderr, "");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_pty_stdin_is_typed() {
+    let mut tool = pty_command("read -r name; echo \"hello $name\"", PtySize::default());
+    tool.stdin = Some("world\n".to_string());
+
+    let result = tool.call_tool().await.unwrap();
+    let outcome: CommandOutcome =
+        serde_json::from_value(result.structured_content.unwrap()).unwrap();
+    assert!(outcome.stdout.ends_with("hello world\n"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_pty_timeout() {
+    let mut tool = pty_command("echo started; sleep 30", PtySize::default());
+    tool.timeout_ms = Some(300);
+
+    let started = std::time::Instant::now();
+    let result = tool.call_tool().await.unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+
+    let outcome: CommandOutcome =
+        serde_json::from_value(result.structured_content.unwrap()).unwrap();
+    assert!(outcome.timed_out);
+    assert_eq!(outcome.stdout, "started\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_pty_rejects_background() {
+    let mut tool = pty_command("echo hi", PtySize::default());
+    tool.background = true;
+
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::InvalidArgument(_))));
+}
+
+#[test]
+fn test_resource_limits_tightest() {
+    let server = ResourceLimits {
+        cpu_time_secs: Some(60),
+        memory_bytes: Some(1 << 30),
+        ..ResourceLimits::default()
+    };
+    let call = ResourceLimits {
+        cpu_time_secs: Some(600),
+        memory_bytes: Some(1 << 20),
+        wall_time_ms: Some(5_000),
+        ..ResourceLimits::default()
+    };
+
+    assert_eq!(
+        call.tightest(&server),
+        ResourceLimits {
+            cpu_time_secs: Some(60),
+            memory_bytes: Some(1 << 20),
+            wall_time_ms: Some(5_000),
+            ..ResourceLimits::default()
+        }
+    );
+}
+
+#[cfg(target_os = "linux")]
+fn limited_command(command: &str, limits: ResourceLimits) -> ExecuteCommandTool {
+    ExecuteCommandTool {
+        limits,
+        timeout_ms: Some(30_000),
+        ..command_tool(Invocation::shell(command))
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_execute_command_file_size_limit() {
+    let temp_dir = TempDir::new().unwrap();
+    let output = temp_dir.path().join("big");
+
+    let tool = limited_command(
+        &format!("head -c 100000 /dev/zero > {}", output.display()),
+        ResourceLimits {
+            max_file_size_bytes: Some(1000),
+            ..ResourceLimits::default()
+        },
+    );
+
+    let result = tool.call_tool().await.unwrap();
+    assert_eq!(result.is_error, Some(true));
+    assert!(fs::metadata(&output).unwrap().len() <= 1000);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_execute_command_cpu_time_limit() {
+    let tool = limited_command(
+        "exec sh -c 'while :; do :; done'",
+        ResourceLimits {
+            cpu_time_secs: Some(1),
+            ..ResourceLimits::default()
+        },
+    );
+
+    let result = tool.call_tool().await.unwrap();
+    let outcome: CommandOutcome =
+        serde_json::from_value(result.structured_content.clone().unwrap()).unwrap();
+    assert!(!outcome.timed_out);
+    assert!(matches!(outcome.signal, Some(24) | Some(9)));
+    assert!(format!("{:?}", result.content[0]).contains("CPU time limit"));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_execute_command_memory_limit() {
+    let tool = limited_command(
+        "dd if=/dev/zero of=/dev/null bs=256M count=1",
+        ResourceLimits {
+            memory_bytes: Some(64 * 1024 * 1024),
+            ..ResourceLimits::default()
+        },
+    );
+
+    let result = tool.call_tool().await.unwrap();
+    assert_eq!(result.is_error, Some(true));
+
+    let tool = limited_command(
+        "dd if=/dev/zero of=/dev/null bs=1M count=1",
+        ResourceLimits {
+            memory_bytes: Some(64 * 1024 * 1024),
+            ..ResourceLimits::default()
+        },
+    );
+    let result = tool.call_tool().await.unwrap();
+    assert_eq!(result.is_error, Some(false));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_execute_command_wall_time_limit() {
+    let mut tool = policy_command("sleep 30", "");
+    tool.limits.wall_time_ms = Some(300);
+
+    let result = tool.call_tool().await.unwrap();
+    let outcome: CommandOutcome =
+        serde_json::from_value(result.structured_content.unwrap()).unwrap();
+    assert!(outcome.timed_out);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_background_process_wall_time_limit() {
+    let processes = ProcessRegistry::new();
+    let mut tool = background_command("echo started; sleep 30", &processes);
+    tool.limits.wall_time_ms = Some(300);
+    tool.call_tool().await.unwrap();
+
+    let output = poll_output_until(&processes, 1, "terminated by signal").await;
+    assert!(output.contains("started"));
+}
+
+#[cfg(unix)]
+async fn create_shell_session(sessions: &ShellSessions, working_directory: &str) -> u32 {
+    let result = ShellSessionCreateTool {
+        shell: Shell::Bash,
+        working_directory: Some(working_directory.to_string()),
+        env: HashMap::from([("GREETING".to_string(), "hello".to_string())]),
+        limits: ResourceLimits::default(),
+        workspace: Workspace::default(),
+        sessions: sessions.clone(),
+    }
+    .call_tool()
+    .await
+    .unwrap();
+
+    let text = result.content[0].as_text().unwrap().text.clone();
+    text.strip_prefix("Started shell session ")
+        .and_then(|rest| rest.split(' ').next())
+        .and_then(|id| id.parse().ok())
+        .unwrap_or_else(|| panic!("unexpected create result: {}", text))
+}
+
+#[cfg(unix)]
+fn session_command(
+    sessions: &ShellSessions,
+    session_id: u32,
+    command: &str,
+) -> ShellSessionRunTool {
+    ShellSessionRunTool {
+        session_id,
+        command: command.to_string(),
+        timeout_ms: Some(10_000),
+        max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        output_dir: None,
+        cancellation_token: CancellationToken::new(),
+        sessions: sessions.clone(),
+        policy: CommandPolicy::allow_all(),
+        peer: None,
+    }
+}
+
+#[cfg(unix)]
+async fn run_in_session(
+    sessions: &ShellSessions,
+    session_id: u32,
+    command: &str,
+) -> ShellCommandOutcome {
+    let result = session_command(sessions, session_id, command)
+        .call_tool()
+        .await
+        .unwrap();
+    serde_json::from_value(result.structured_content.unwrap()).unwrap()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_shell_session_keeps_directory_and_environment() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("sub")).unwrap();
+    fs::write(
+        temp_dir.path().join("sub").join("activate"),
+        "export ACTIVATED=yes\n",
+    )
+    .unwrap();
+    let sessions = ShellSessions::new();
+    let id = create_shell_session(&sessions, &temp_dir.path().to_string_lossy()).await;
+
+    let outcome = run_in_session(&sessions, id, "cd sub && export NAME=world").await;
+    assert_eq!(outcome.exit_code, Some(0));
+    let sub = temp_dir.path().join("sub").canonicalize().unwrap();
+    assert_eq!(
+        outcome.working_directory.as_deref(),
+        Some(sub.to_string_lossy().as_ref())
+    );
+
+    run_in_session(&sessions, id, "source ./activate").await;
+    let outcome = run_in_session(&sessions, id, "echo \"$GREETING $NAME $ACTIVATED\"; pwd").await;
+    assert_eq!(
+        outcome.stdout,
+        format!("hello world yes\n{}\n", sub.to_string_lossy())
+    );
+    assert!(!outcome.session_closed);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_shell_session_delimits_output_and_status() {
+    let temp_dir = TempDir::new().unwrap();
+    let sessions = ShellSessions::new();
+    let id = create_shell_session(&sessions, &temp_dir.path().to_string_lossy()).await;
+
+    let result = session_command(&sessions, id, "printf partial; printf oops >&2; false")
+        .call_tool()
+        .await
+        .unwrap();
+    assert_eq!(result.is_error, Some(true));
+    let outcome: ShellCommandOutcome =
+        serde_json::from_value(result.structured_content.unwrap()).unwrap();
+    assert_eq!(outcome.exit_code, Some(1));
+    assert_eq!(outcome.stdout, "partial");
+    assert_eq!(outcome.stderr, "oops");
+
+    // Quotes and a stray read of stdin don't confuse the session.
+    let outcome = run_in_session(&sessions, id, "echo 'it'\\''s'; cat; echo done").await;
+    assert_eq!(outcome.exit_code, Some(0));
+    assert_eq!(outcome.stdout, "it's\ndone\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_shell_session_exit_closes_session() {
+    let temp_dir = TempDir::new().unwrap();
+    let sessions = ShellSessions::new();
+    let id = create_shell_session(&sessions, &temp_dir.path().to_string_lossy()).await;
+
+    let outcome = run_in_session(&sessions, id, "echo bye; exit 3").await;
+    assert!(outcome.session_closed);
+    assert_eq!(outcome.stdout, "bye\n");
+
+    let result = session_command(&sessions, id, "true").call_tool().await;
+    assert!(matches!(result, Err(ToolError::InvalidArgument(_))));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_shell_session_timeout_closes_session() {
+    let temp_dir = TempDir::new().unwrap();
+    let sessions = ShellSessions::new();
+    let id = create_shell_session(&sessions, &temp_dir.path().to_string_lossy()).await;
+
+    let mut tool = session_command(&sessions, id, "echo started; sleep 30");
+    tool.timeout_ms = Some(300);
+    let started = std::time::Instant::now();
+    let result = tool.call_tool().await.unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+
+    let outcome: ShellCommandOutcome =
+        serde_json::from_value(result.structured_content.unwrap()).unwrap();
+    assert!(outcome.timed_out);
+    assert!(outcome.session_closed);
+    assert_eq!(outcome.stdout, "started\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_shell_session_close() {
+    let temp_dir = TempDir::new().unwrap();
+    let sessions = ShellSessions::new();
+    let id = create_shell_session(&sessions, &temp_dir.path().to_string_lossy()).await;
+
+    let close = ShellSessionCloseTool {
+        session_id: id,
+        sessions: sessions.clone(),
+    };
+    close.call_tool().await.unwrap();
+    assert!(matches!(
+        close.call_tool().await,
+        Err(ToolError::InvalidArgument(_))
+    ));
+
+    let result = session_command(&sessions, id, "true").call_tool().await;
+    assert!(matches!(result, Err(ToolError::InvalidArgument(_))));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_shell_session_policy_uses_session_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("protected")).unwrap();
+    let sessions = ShellSessions::new();
+    let id = create_shell_session(&sessions, &temp_dir.path().to_string_lossy()).await;
+    run_in_session(&sessions, id, "cd protected").await;
+
+    let mut tool = session_command(&sessions, id, "touch marker");
+    tool.policy = CommandPolicy::from_toml(
+        "[[rules]]\naction = \"deny\"\nworking_directory = \"**/protected\"",
+    )
+    .unwrap();
+
+    let result = tool.call_tool().await;
+    assert!(matches!(result, Err(ToolError::PolicyViolation(_))));
+    assert!(!temp_dir.path().join("protected").join("marker").exists());
 }